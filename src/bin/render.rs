@@ -0,0 +1,301 @@
+use std::env;
+use std::io;
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
+
+use opengl::render::{Projection, RenderOptions, Shading};
+use opengl::{Color, CullMode, Filter, Image, ImageFormat, Model, NormalMap, Renderer, Texture, TgaPixels, Vec3, Vec3f, Wrap};
+
+const USAGE: &str = "Usage: render [OPTIONS] [MODEL]
+
+Renders a Wavefront OBJ, STL or PLY model (default: african_head.obj, - reads standard
+input) into an image.
+
+Options:
+  -o, --output PATH        output file, TGA, PNG, PPM or PGM by extension (default: output.tga)
+  -s, --size WxH           image resolution (default: 800x800)
+      --rle / --no-rle     enable or disable TGA RLE compression (default: enabled)
+      --color-map          write TGA with a color map when the image has at most
+                           256 colors
+      --tga16              write 16-bit A1R5G5B5 TGA
+      --ascii              write PPM/PGM as plain text
+      --shading MODE       flat, gouraud, phong, normals or wireframe
+                           (default: flat)
+      --cull MODE          face culling: back, front or none (default: back)
+      --light X,Y,Z        light direction (default: 0,0,-1)
+      --eye X,Y,Z          camera position (default: 0,0,3)
+      --center X,Y,Z       point the camera looks at (default: 0,0,0)
+      --up X,Y,Z           camera up vector (default: 0,1,0)
+      --projection MODE    perspective or orthographic (default: perspective)
+      --background R,G,B   background color (default: 0,0,0)
+      --texture PATH       diffuse texture (TGA, PNG, PPM/PGM or BMP)
+      --normal-map PATH    normal map, used by phong shading
+      --normal-space MODE  normal map space: tangent or object (default: tangent)
+      --specular PATH      specular map, used by phong shading
+      --shadows            enable shadow mapping
+      --shadow-bias F      shadow map depth bias (default: 0.01)
+      --pcf N              shadow filtering radius in pixels, 0 for hard shadows (default: 1)
+      --ssao               enable screen-space ambient occlusion
+      --ssao-radius F      occlusion search radius in pixels (default: 16)
+      --ssao-samples N     number of occlusion search directions (default: 8)
+      --filter MODE        texture filtering: nearest or bilinear (default: bilinear)
+      --wrap MODE          texture addressing: repeat or clamp (default: repeat)
+  -h, --help               print this help";
+
+struct Args {
+    model: String,
+    output: String,
+    width: usize,
+    height: usize,
+    rle: bool,
+    tga_pixels: TgaPixels,
+    ascii: bool,
+    texture: Option<String>,
+    normal_map: Option<String>,
+    object_normals: bool,
+    specular: Option<String>,
+    filter: Filter,
+    wrap: Wrap,
+    opts: RenderOptions,
+}
+
+fn parse_number<T: FromStr>(opt: &str, s: &str) -> Result<T, String> {
+    s.trim().parse().map_err(|_| format!("{}: invalid number '{}'", opt, s))
+}
+
+fn parse_triple<T: FromStr>(opt: &str, s: &str) -> Result<[T; 3], String> {
+    let parts: Vec<&str> = s.split(',').collect();
+    if parts.len() != 3 {
+        return Err(format!("{}: expected three comma-separated values, got '{}'", opt, s));
+    }
+    Ok([
+        parse_number(opt, parts[0])?,
+        parse_number(opt, parts[1])?,
+        parse_number(opt, parts[2])?,
+    ])
+}
+
+fn parse_vec3(opt: &str, s: &str) -> Result<Vec3f, String> {
+    let [x, y, z] = parse_triple(opt, s)?;
+    Ok(Vec3::new(x, y, z))
+}
+
+fn parse_size(opt: &str, s: &str) -> Result<(usize, usize), String> {
+    let mut parts = s.splitn(2, 'x');
+    let w = parse_number(opt, parts.next().unwrap_or(""))?;
+    let h = parse_number(opt, parts.next().ok_or_else(|| format!("{}: expected WxH, got '{}'", opt, s))?)?;
+    if w == 0 || h == 0 || w > i16::MAX as usize || h > i16::MAX as usize {
+        return Err(format!("{}: resolution must be between 1 and {}", opt, i16::MAX));
+    }
+    Ok((w, h))
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut it: I) -> Result<Option<Args>, String> {
+    let mut args = Args {
+        model: "african_head.obj".to_string(),
+        output: "output.tga".to_string(),
+        width: 800,
+        height: 800,
+        rle: true,
+        tga_pixels: TgaPixels::TrueColor,
+        ascii: false,
+        texture: None,
+        normal_map: None,
+        object_normals: false,
+        specular: None,
+        filter: Filter::Bilinear,
+        wrap: Wrap::Repeat,
+        opts: Default::default(),
+    };
+    let mut model = None;
+
+    while let Some(arg) = it.next() {
+        let mut value = |opt: &str| it.next().ok_or_else(|| format!("{}: missing value", opt));
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-o" | "--output" => args.output = value(&arg)?,
+            "-s" | "--size" => {
+                let (w, h) = parse_size(&arg, &value(&arg)?)?;
+                args.width = w;
+                args.height = h;
+            },
+            "--rle" => args.rle = true,
+            "--no-rle" => args.rle = false,
+            "--color-map" => args.tga_pixels = TgaPixels::ColorMapped,
+            "--tga16" => args.tga_pixels = TgaPixels::HighColor,
+            "--ascii" => args.ascii = true,
+            "--shading" => {
+                args.opts.shading = match value(&arg)?.as_str() {
+                    "flat" => Shading::Flat,
+                    "gouraud" => Shading::Gouraud,
+                    "phong" => Shading::Phong,
+                    "normals" => Shading::Normals,
+                    "wireframe" => Shading::Wireframe,
+                    other => return Err(format!("{}: unknown shading mode '{}'", arg, other)),
+                }
+            },
+            "--cull" => {
+                args.opts.cull = match value(&arg)?.as_str() {
+                    "back" => CullMode::Back,
+                    "front" => CullMode::Front,
+                    "none" => CullMode::None,
+                    other => return Err(format!("{}: unknown cull mode '{}'", arg, other)),
+                }
+            },
+            "--light" => args.opts.light_dir = parse_vec3(&arg, &value(&arg)?)?,
+            "--eye" => args.opts.camera.eye = parse_vec3(&arg, &value(&arg)?)?,
+            "--center" => args.opts.camera.center = parse_vec3(&arg, &value(&arg)?)?,
+            "--up" => args.opts.camera.up = parse_vec3(&arg, &value(&arg)?)?,
+            "--projection" => {
+                args.opts.camera.projection = match value(&arg)?.as_str() {
+                    "perspective" => Projection::Perspective,
+                    "orthographic" => Projection::Orthographic,
+                    other => return Err(format!("{}: unknown projection '{}'", arg, other)),
+                }
+            },
+            "--background" => {
+                let [r, g, b] = parse_triple(&arg, &value(&arg)?)?;
+                args.opts.background = Color::rgba(r, g, b, 255);
+            },
+            "--ssao" => {
+                args.opts.ssao.get_or_insert_with(Default::default);
+            },
+            "--ssao-radius" => {
+                let radius = parse_number(&arg, &value(&arg)?)?;
+                args.opts.ssao.get_or_insert_with(Default::default).radius = radius;
+            },
+            "--ssao-samples" => {
+                let samples = parse_number(&arg, &value(&arg)?)?;
+                args.opts.ssao.get_or_insert_with(Default::default).samples = samples;
+            },
+            "--texture" => args.texture = Some(value(&arg)?),
+            "--normal-map" => args.normal_map = Some(value(&arg)?),
+            "--normal-space" => {
+                args.object_normals = match value(&arg)?.as_str() {
+                    "tangent" => false,
+                    "object" => true,
+                    other => return Err(format!("{}: unknown normal map space '{}'", arg, other)),
+                }
+            },
+            "--specular" => args.specular = Some(value(&arg)?),
+            "--shadows" => {
+                args.opts.shadows.get_or_insert_with(Default::default);
+            },
+            "--shadow-bias" => {
+                let bias = parse_number(&arg, &value(&arg)?)?;
+                args.opts.shadows.get_or_insert_with(Default::default).bias = bias;
+            },
+            "--pcf" => {
+                let radius = parse_number(&arg, &value(&arg)?)?;
+                args.opts.shadows.get_or_insert_with(Default::default).pcf_radius = radius;
+            },
+            "--filter" => {
+                args.filter = match value(&arg)?.as_str() {
+                    "nearest" => Filter::Nearest,
+                    "bilinear" => Filter::Bilinear,
+                    other => return Err(format!("{}: unknown filter '{}'", arg, other)),
+                }
+            },
+            "--wrap" => {
+                args.wrap = match value(&arg)?.as_str() {
+                    "repeat" => Wrap::Repeat,
+                    "clamp" => Wrap::Clamp,
+                    other => return Err(format!("{}: unknown wrap mode '{}'", arg, other)),
+                }
+            },
+            s if s.starts_with('-') && s != "-" => return Err(format!("unknown option '{}'", s)),
+            _ => {
+                if model.is_some() {
+                    return Err(format!("unexpected argument '{}'", arg));
+                }
+                model = Some(arg);
+            },
+        }
+    }
+    if let Some(model) = model {
+        args.model = model;
+    }
+    if args.opts.light_dir.norm() == 0. {
+        return Err("--light: direction must be non-zero".to_string());
+    }
+    if (args.opts.camera.eye - args.opts.camera.center).norm() == 0. {
+        return Err("--eye and --center must differ".to_string());
+    }
+    let view = args.opts.camera.eye - args.opts.camera.center;
+    if (view ^ args.opts.camera.up).norm() == 0. {
+        return Err("--up must be non-zero and not parallel to the line from --center to --eye".to_string());
+    }
+    Ok(Some(args))
+}
+
+fn load_texture(path: &str, args: &Args) -> Result<Texture, String> {
+    let mut tex = Texture::read_file(path)
+        .map_err(|e| format!("cannot load texture '{}': {}", path, e))?;
+    tex.filter = args.filter;
+    tex.wrap = args.wrap;
+    Ok(tex)
+}
+
+fn run(mut args: Args) -> Result<(), String> {
+    if let Some(ref path) = args.texture {
+        args.opts.diffuse = Some(load_texture(path, &args)?);
+    }
+    if let Some(ref path) = args.normal_map {
+        let tex = load_texture(path, &args)?;
+        args.opts.normal_map = Some(if args.object_normals { NormalMap::Object(tex) } else { NormalMap::Tangent(tex) });
+    }
+    if let Some(ref path) = args.specular {
+        args.opts.specular = Some(load_texture(path, &args)?);
+    }
+
+    let model = if args.model == "-" {
+        Model::from_reader(&mut io::stdin().lock())
+    } else {
+        Model::new(&args.model)
+    };
+    let model = model
+        .map_err(|e| format!("cannot load model '{}': {}", args.model, e))?;
+    println!("Loaded {} faces, {} verts, {} uvs, {} normals, {} groups, {} materials",
+             model.nfaces(), model.nverts(), model.nuvs(), model.nnorms(),
+             model.groups().len(), model.materials().len());
+    for mat in model.materials() {
+        for path in [&mat.diffuse_map, &mat.bump_map, &mat.specular_map].into_iter().flatten() {
+            if !path.is_file() {
+                eprintln!("warning: texture '{}' of material '{}' not found", path.display(), mat.name);
+            }
+        }
+    }
+
+    let mut img = Image::new(args.width, args.height, 3);
+    img.render(&model, &args.opts)
+        .and_then(|_| img.flip_vertically())
+        .map_err(|e| format!("cannot render model '{}': {}", args.model, e))?;
+    let format = match ImageFormat::from_extension(Path::new(&args.output)) {
+        Some(ImageFormat::Ppm {..}) => ImageFormat::Ppm {ascii: args.ascii},
+        Some(ImageFormat::Pgm {..}) => ImageFormat::Pgm {ascii: args.ascii},
+        Some(ImageFormat::Png) => ImageFormat::Png,
+        Some(ImageFormat::Tga {..}) | None => ImageFormat::Tga {rle: args.rle, pixels: args.tga_pixels},
+    };
+    img.write_file(&args.output, format)
+        .map_err(|e| format!("cannot write image '{}': {}", args.output, e))?;
+    Ok(())
+}
+
+fn main() {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        },
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            process::exit(2);
+        },
+    };
+    if let Err(e) = run(args) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,288 @@
+use std::io::Read;
+use crate::error::{Error, Result};
+use crate::tgaimage::Image;
+
+const BI_RGB: u32 = 0;
+const BI_RLE8: u32 = 1;
+const BI_RLE4: u32 = 2;
+const BI_BITFIELDS: u32 = 3;
+const BI_ALPHABITFIELDS: u32 = 6;
+
+fn u16_at(data: &[u8], pos: usize) -> Result<u16> {
+    data.get(pos..pos + 2).map(|b| u16::from_le_bytes([b[0], b[1]])).ok_or(Error::InvalidHeader)
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Result<u32> {
+    data.get(pos..pos + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])).ok_or(Error::InvalidHeader)
+}
+
+// Channel stored under a bit mask, scaled to 0..255
+#[inline]
+fn channel(px: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+    let bits = mask.count_ones();
+    let v = (px & mask) >> mask.trailing_zeros();
+    if bits >= 8 {
+        (v >> (bits - 8)) as u8
+    } else {
+        (v * 255 / ((1 << bits) - 1)) as u8
+    }
+}
+
+// Hands every decoded palette index to put with its column and row in file order, pixels skipped
+// by deltas or early line ends are left alone
+fn decode_rle(data: &[u8], w: usize, h: usize, four_bit: bool,
+              mut put_index: impl FnMut(usize, usize, u8) -> Result<()>) -> Result<()> {
+    let (mut x, mut y, mut pos) = (0usize, 0usize, 0usize);
+    let mut put = |x: &mut usize, y: usize, v: u8| -> Result<()> {
+        if *x < w && y < h {
+            put_index(*x, y, v)?;
+        }
+        *x += 1;
+        Ok(())
+    };
+    loop {
+        let (n, v) = match data.get(pos..pos + 2) {
+            Some(b) => (b[0] as usize, b[1]),
+            None => return Err(Error::InvalidFormat("Truncated BMP RLE data")),
+        };
+        pos += 2;
+        match (n, v) {
+            (0, 0) => { x = 0; y += 1; },
+            (0, 1) => break,
+            (0, 2) => {
+                let d = data.get(pos..pos + 2).ok_or(Error::InvalidFormat("Truncated BMP RLE data"))?;
+                x += d[0] as usize;
+                y += d[1] as usize;
+                pos += 2;
+            },
+            (0, count) => {
+                let count = count as usize;
+                let size = if four_bit { count.div_ceil(2) } else { count };
+                let run = data.get(pos..pos + size).ok_or(Error::InvalidFormat("Truncated BMP RLE data"))?;
+                for i in 0..count {
+                    let v = if four_bit { (run[i / 2] >> (4 - 4 * (i % 2))) & 0xf } else { run[i] };
+                    put(&mut x, y, v)?;
+                }
+                // Absolute runs are padded to a 16-bit boundary
+                pos += size + size % 2;
+            },
+            (count, v) => {
+                for i in 0..count {
+                    put(&mut x, y, if four_bit { (v >> (4 - 4 * (i % 2))) & 0xf } else { v })?;
+                }
+            },
+        }
+        if y >= h {
+            break;
+        }
+    }
+    Ok(())
+}
+
+impl Image {
+    // Windows and OS/2 bitmaps: 1, 4, 8, 16, 24 and 32 bits per pixel, uncompressed, RLE8/RLE4 or
+    // with bit fields. Paletted and RGB images give 3 bytes per pixel, an alpha mask gives 4.
+    pub fn read_bmp(f: &mut dyn Read) -> Result<Self> {
+        let mut data = Vec::new();
+        f.read_to_end(&mut data)?;
+        if !data.starts_with(b"BM") {
+            return Err(Error::InvalidHeader);
+        }
+        let offset = u32_at(&data, 10)? as usize;
+        let header_size = u32_at(&data, 14)? as usize;
+
+        let (w, h, top_down, bpp, compression, colors_used) = if header_size == 12 {
+            (u16_at(&data, 18)? as i64, u16_at(&data, 20)? as i64, false, u16_at(&data, 24)?, BI_RGB, 0)
+        } else if header_size >= 40 {
+            let h = u32_at(&data, 22)? as i32 as i64;
+            (u32_at(&data, 18)? as i32 as i64, h.abs(), h < 0, u16_at(&data, 28)?, u32_at(&data, 30)?, u32_at(&data, 46)? as usize)
+        } else {
+            return Err(Error::InvalidHeader);
+        };
+        if w <= 0 || h <= 0 || w > i32::MAX as i64 || h > i32::MAX as i64 {
+            return Err(Error::InvalidHeader);
+        }
+        let (w, h) = (w as usize, h as usize);
+        let valid = match compression {
+            BI_RGB => [1, 4, 8, 16, 24, 32].contains(&bpp),
+            BI_RLE8 => bpp == 8,
+            BI_RLE4 => bpp == 4,
+            BI_BITFIELDS | BI_ALPHABITFIELDS => bpp == 16 || bpp == 32,
+            _ => false,
+        };
+        if !valid {
+            return Err(Error::InvalidFormat("Unsupported BMP compression or bit depth"));
+        }
+
+        // Red, green, blue and alpha masks follow a plain info header, later versions include them
+        let mut table = 14 + header_size;
+        let masks = match compression {
+            BI_BITFIELDS | BI_ALPHABITFIELDS => {
+                let base = if header_size == 40 { table } else { 54 };
+                let with_alpha = compression == BI_ALPHABITFIELDS || header_size >= 56;
+                if header_size == 40 {
+                    table += if with_alpha { 16 } else { 12 };
+                }
+                [u32_at(&data, base)?, u32_at(&data, base + 4)?, u32_at(&data, base + 8)?,
+                    if with_alpha { u32_at(&data, base + 12)? } else { 0 }]
+            },
+            _ if bpp == 16 => [0x7c00, 0x03e0, 0x001f, 0],
+            // The fourth byte of 32-bit BI_RGB pixels is unused and often left zero
+            _ => [0xff0000, 0xff00, 0xff, 0],
+        };
+
+        let palette: Vec<[u8; 3]> = if bpp <= 8 {
+            let entry = if header_size == 12 { 3 } else { 4 };
+            let mut count = if colors_used == 0 { 1 << bpp } else { colors_used.min(1 << bpp) };
+            // Some writers store fewer entries than the bit depth allows, the pixel data offset tells
+            if offset > table {
+                count = count.min((offset - table) / entry);
+            }
+            (0..count).map(|i| {
+                data.get(table + i * entry..table + i * entry + 3).map(|c| [c[0], c[1], c[2]])
+                    .ok_or(Error::InvalidFormat("Truncated BMP palette"))
+            }).collect::<Result<_>>()?
+        } else {
+            Vec::new()
+        };
+        let lookup = |i: u8| palette.get(i as usize).copied().ok_or(Error::InvalidFormat("BMP palette index out of range"));
+
+        let pixels = data.get(offset..).ok_or(Error::InvalidFormat("Truncated BMP image data"))?;
+        let bytespp = if masks[3] != 0 { 4 } else { 3 };
+        let rle = compression == BI_RLE8 || compression == BI_RLE4;
+        // Rows are padded to a multiple of 4 bytes
+        let stride = (w * bpp as usize).div_ceil(32) * 4;
+        let size = stride.checked_mul(h - 1).and_then(|n| n.checked_add((w * bpp as usize).div_ceil(8)));
+        if !rle && size.is_none_or(|size| pixels.len() < size) {
+            return Err(Error::InvalidFormat("Truncated BMP image data"));
+        }
+        // A run covers at most 255 pixels per 2 bytes. Deltas and the end of bitmap marker could
+        // skip any number of them, RLE images mostly made of skipped pixels are not accepted.
+        let max_pixels = if rle { pixels.len().saturating_mul(255) } else { usize::MAX };
+        if w.checked_mul(h).filter(|&n| n <= max_pixels).and_then(|n| n.checked_mul(bytespp)).is_none() {
+            return Err(Error::InvalidFormat("BMP image too large"));
+        }
+        let mut image = Image::new(w, h, bytespp);
+        let row_of = |r: usize| if top_down { r } else { h - 1 - r };
+
+        if rle {
+            // Skipped pixels get the first palette color
+            if let Some(first) = palette.first() {
+                image.data.chunks_exact_mut(3).for_each(|px| px.copy_from_slice(first));
+            }
+            decode_rle(pixels, w, h, compression == BI_RLE4, |x, r, i| {
+                image.data[(row_of(r) * w + x) * 3..][..3].copy_from_slice(&lookup(i)?);
+                Ok(())
+            })?;
+            return Ok(image);
+        }
+
+        for r in 0..h {
+            let src = &pixels[r * stride..];
+            let y = row_of(r);
+            for x in 0..w {
+                let bgra = match bpp {
+                    1 | 4 | 8 => {
+                        let bits = bpp as usize;
+                        let bit = x * bits;
+                        let i = (src[bit / 8] >> (8 - bits - bit % 8)) & ((1u16 << bits) - 1) as u8;
+                        let [b, g, r] = lookup(i)?;
+                        [b, g, r, 255]
+                    },
+                    24 => [src[3 * x], src[3 * x + 1], src[3 * x + 2], 255],
+                    _ => {
+                        let px = if bpp == 16 {
+                            u16::from_le_bytes([src[2 * x], src[2 * x + 1]]) as u32
+                        } else {
+                            u32::from_le_bytes([src[4 * x], src[4 * x + 1], src[4 * x + 2], src[4 * x + 3]])
+                        };
+                        [channel(px, masks[2]), channel(px, masks[1]), channel(px, masks[0]), channel(px, masks[3])]
+                    },
+                };
+                image.data[(y * w + x) * bytespp..][..bytespp].copy_from_slice(&bgra[..bytespp]);
+            }
+        }
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BI_RGB, BI_RLE4, BI_RLE8};
+    use crate::tgaimage::Image;
+
+    // A bitmap with a 40-byte info header, palette entries i get the color (10i, 20i, 30i) in BGR order
+    fn bmp(w: i32, h: i32, bpp: u16, compression: u32, colors: u32, pixels: &[u8]) -> Vec<u8> {
+        let offset = 54 + 4 * colors;
+        let mut out = b"BM".to_vec();
+        out.extend((offset + pixels.len() as u32).to_le_bytes());
+        out.extend([0; 4]);
+        out.extend(offset.to_le_bytes());
+        out.extend(40u32.to_le_bytes());
+        out.extend(w.to_le_bytes());
+        out.extend(h.to_le_bytes());
+        out.extend(1u16.to_le_bytes());
+        out.extend(bpp.to_le_bytes());
+        out.extend(compression.to_le_bytes());
+        out.extend([0; 12]);
+        out.extend(colors.to_le_bytes());
+        out.extend([0; 4]);
+        for i in 0..colors as u8 {
+            out.extend([10 * i, 20 * i, 30 * i, 0]);
+        }
+        out.extend(pixels);
+        out
+    }
+
+    fn decode(data: &[u8]) -> Image {
+        Image::read_bmp(&mut &data[..]).unwrap()
+    }
+
+    fn indices(image: &Image) -> Vec<u8> {
+        image.data.chunks_exact(3).map(|px| px[0] / 10).collect()
+    }
+
+    #[test]
+    fn uncompressed() {
+        // Bottom-up 24-bit rows padded to 8 bytes, then a top-down 1-bit image
+        let image = decode(&bmp(2, 2, 24, BI_RGB, 0, &[1, 2, 3, 4, 5, 6, 0, 0, 7, 8, 9, 10, 11, 12, 0, 0]));
+        assert_eq!(image.data, [7, 8, 9, 10, 11, 12, 1, 2, 3, 4, 5, 6]);
+        let image = decode(&bmp(3, -2, 1, BI_RGB, 2, &[0b101_00000, 0, 0, 0, 0b010_00000, 0, 0, 0]));
+        assert_eq!(indices(&image), [1, 0, 1, 0, 1, 0]);
+    }
+
+    #[test]
+    fn rle8() {
+        // A run, an absolute run with padding, then a delta leaving two pixels of the last row alone
+        let data = [3, 1, 0, 0, 0, 3, 2, 3, 1, 0, 0, 0, 0, 2, 2, 0, 1, 3, 0, 1];
+        let image = decode(&bmp(3, 3, 8, BI_RLE8, 4, &data));
+        assert_eq!(indices(&image), [0, 0, 3, 2, 3, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn rle4() {
+        // Alternating nibbles in a run, a delta to the second column and an absolute run of three
+        let data = [4, 0x12, 0, 0, 0, 2, 1, 0, 0, 3, 0x30, 0x10, 0, 1];
+        let image = decode(&bmp(4, 2, 4, BI_RLE4, 4, &data));
+        assert_eq!(indices(&image), [0, 3, 0, 1, 1, 2, 1, 2]);
+    }
+
+    #[test]
+    fn invalid_files() {
+        let read = |data: &[u8]| Image::read_bmp(&mut &data[..]);
+        // Missing row data, dimensions nothing can hold and a missing header
+        assert!(read(&bmp(2, 2, 24, BI_RGB, 0, &[0; 12])).is_err());
+        assert!(read(&bmp(i32::MAX, i32::MAX, 32, BI_RGB, 0, &[0; 16])).is_err());
+        assert!(read(&bmp(2, 2, 24, BI_RGB, 0, &[0; 16])[..30]).is_err());
+        // RLE data without an end of bitmap marker, an index past the palette and a huge image
+        // made almost entirely of skipped pixels
+        assert!(read(&bmp(4, 2, 8, BI_RLE8, 4, &[4, 1, 0, 0])).is_err());
+        assert!(read(&bmp(4, 2, 8, BI_RLE8, 4, &[4, 7, 0, 1])).is_err());
+        assert!(read(&bmp(16000, 16000, 8, BI_RLE8, 4, &[0, 2, 255, 255, 0, 1])).is_err());
+        // Bit depths the compression does not allow
+        assert!(read(&bmp(4, 2, 4, BI_RLE8, 4, &[0, 1])).is_err());
+    }
+}
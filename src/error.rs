@@ -0,0 +1,91 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::result;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    InvalidHeader,
+    InvalidFormat(&'static str),
+    Parse(ParseError),
+    WrongCoords,
+}
+
+// Where and why a text asset (OBJ, MTL) could not be read, token is empty at the end of a line.
+// There is no file when the asset comes from a reader.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub file: Option<PathBuf>,
+    pub line: usize,
+    pub token: String,
+    pub expected: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.file {
+            Some(ref file) => write!(f, "{}:{}: ", file.display(), self.line)?,
+            None => write!(f, "line {}: ", self.line)?,
+        }
+        write!(f, "expected {}, ", self.expected)?;
+        if self.token.is_empty() {
+            write!(f, "found end of line")
+        } else {
+            write!(f, "found '{}'", self.token)
+        }
+    }
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+            Error::InvalidHeader => write!(f, "Invalid header format"),
+            Error::InvalidFormat(desc) => write!(f, "Invalid file format: {}", desc),
+            Error::Parse(ref e) => write!(f, "Parse error: {}", e),
+            Error::WrongCoords => write!(f, "Wrong coords"),
+        }
+    }
+}
+
+// Current position in a text asset, turns offending tokens into parse errors
+pub(crate) struct Location<'a> {
+    pub file: Option<&'a Path>,
+    pub line: usize,
+}
+
+impl Location<'_> {
+    pub fn error<S: Into<String>>(&self, token: &str, expected: S) -> Error {
+        Error::Parse(ParseError {
+            file: self.file.map(Path::to_path_buf),
+            line: self.line,
+            token: token.to_string(),
+            expected: expected.into(),
+        })
+    }
+
+    pub fn parse<T: FromStr>(&self, token: Option<&&str>, expected: &str) -> Result<T> {
+        let token = token.copied().unwrap_or("");
+        token.parse().map_err(|_| self.error(token, expected))
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
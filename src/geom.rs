@@ -1,22 +1,32 @@
-use tgaimage::{Color, Image};
-use zbuffer::ZBuffer;
-use std::old_io::IoResult;
+use crate::tgaimage::{Color, Image};
+use crate::shader::Shader;
+use crate::zbuffer::{Depth, ZBuffer};
+use crate::error::Result;
 use std::mem::swap;
-use std::num::{SignedInt, NumCast};
-pub use vec::*;
+pub use crate::vec::*;
 
 pub trait GeomActions {
-    fn line<T: NumCast>(&mut self, from: Vec2<T>, to: Vec2<T>, c: &Color) -> IoResult<()>;
-    fn triangle(&mut self, mut t0: Vec3f, mut t1: Vec3f, mut t2: Vec3f, c: &Color, zbuf: &mut ZBuffer) -> IoResult<()>;
+    fn line<T: Scalar>(&mut self, from: Vec2<T>, to: Vec2<T>, c: &Color) -> Result<()>;
+    fn triangle<D: Depth>(&mut self, t0: Vec3f, t1: Vec3f, t2: Vec3f, c: &Color, zbuf: &mut ZBuffer<D>) -> Result<()>;
+    fn rasterize<V, D, F>(&mut self, pts: &[Vec3f; 3], w: &[f32; 3], varyings: &[V; 3], zbuf: &mut ZBuffer<D>, shade: F) -> Result<()>
+    where V: Interpolate, D: Depth, F: FnMut(&V) -> Option<Color>;
+    fn draw_face<S: Shader, D: Depth>(&mut self, shader: &mut S, face: usize, cull: CullMode, zbuf: &mut ZBuffer<D>) -> Result<()>;
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum CullMode {
+    None,
+    Back,
+    Front,
 }
 
 impl GeomActions for Image {
-    fn line<T: NumCast>(&mut self, from: Vec2<T>, to: Vec2<T>, c: &Color) -> IoResult<()> {
+    fn line<T: Scalar>(&mut self, from: Vec2<T>, to: Vec2<T>, c: &Color) -> Result<()> {
         let mut steep = false;
-        let mut x0: isize = NumCast::from(from.x).unwrap();
-        let mut x1: isize = NumCast::from(to.x).unwrap();
-        let mut y0: isize = NumCast::from(from.y).unwrap();
-        let mut y1: isize = NumCast::from(to.y).unwrap();
+        let mut x0: isize = from.x.cast();
+        let mut x1: isize = to.x.cast();
+        let mut y0: isize = from.y.cast();
+        let mut y1: isize = to.y.cast();
 
         if (x0 - x1).abs() < (y0 - y1).abs() {
             swap(&mut x0, &mut y0);
@@ -36,11 +46,10 @@ impl GeomActions for Image {
         let mut error2 = 0;
         let mut y = y0;
 
-        for x in range(x0, x1 + 1) {
-            if steep {
-                try!(self.set(y as usize, x as usize, c));
-            } else {
-                try!(self.set(x as usize, y as usize, c));
+        for x in x0..=x1 {
+            let (px, py) = if steep { (y, x) } else { (x, y) };
+            if px >= 0 && py >= 0 && (px as usize) < self.width && (py as usize) < self.height {
+                self.set(px as usize, py as usize, c)?;
             }
             error2 += derror2;
 
@@ -52,58 +61,237 @@ impl GeomActions for Image {
         Ok(())
     }
 
-    fn triangle(&mut self, mut t0: Vec3f, mut t1: Vec3f, mut t2: Vec3f, c: &Color, zbuf: &mut ZBuffer) -> IoResult<()> {
-        if t0.y == t1.y && t0.y == t2.y {
-            //return Err(IoError{kind: IoErrorKind::InvalidInput, desc: "Degenerated triangle", detail: None})
+    fn triangle<D: Depth>(&mut self, t0: Vec3f, t1: Vec3f, t2: Vec3f, c: &Color, zbuf: &mut ZBuffer<D>) -> Result<()> {
+        self.rasterize(&[t0, t1, t2], &[1., 1., 1.], &[(), (), ()], zbuf, |_| Some(c.clone()))
+    }
+
+    fn rasterize<V, D, F>(&mut self, pts: &[Vec3f; 3], w: &[f32; 3], varyings: &[V; 3], zbuf: &mut ZBuffer<D>, mut shade: F) -> Result<()>
+    where V: Interpolate, D: Depth, F: FnMut(&V) -> Option<Color> {
+        if self.width == 0 || self.height == 0 {
             return Ok(())
         }
-        if t0.y > t1.y {
-            swap(&mut t0, &mut t1);
-        }
-        if t0.y > t2.y {
-            swap(&mut t0, &mut t2);
-        }
-        if t1.y > t2.y {
-            swap(&mut t1, &mut t2);
+        // Walk the vertices counter-clockwise so that inside points give positive edge functions
+        let area = edge(&pts[0], &pts[1], &pts[2]);
+        if area == 0. || !area.is_finite() {
+            return Ok(())
         }
+        let order = if area > 0. { [0, 1, 2] } else { [0, 2, 1] };
+        let area = area.abs();
+        let p = [pts[order[0]], pts[order[1]], pts[order[2]]];
 
-        let total_height = t2.y - t0.y;
-
-        for i in range(0us, total_height as usize) {
-            let second_half = i as f32 > t1.y - t0.y || t1.y == t0.y;
-            let segment_height = if second_half { t2.y - t1.y } else { t1.y - t0.y };
-            let alpha = i as f32 / total_height;
-            let beta = if second_half {
-                (i as f32 + t0.y - t1.y) / segment_height
-            } else {
-                i as f32 / segment_height
-            };
-
-            let mut a: Vec3i = (t0 + (t2 - t0) * alpha).to();
-            let mut b: Vec3i = if second_half {
-                (t1 + (t2 - t1) * beta).to()
-            } else {
-                (t0 + (t1 - t0) * beta).to()
-            };
-
-            if a.x > b.x {
-                swap(&mut a, &mut b);
-            }
+        // Pixels exactly on an edge belong to the triangle only if the edge is a top or left one,
+        // so the triangles sharing it don't both draw the pixel
+        let top_left = [is_top_left(&p[1], &p[2]), is_top_left(&p[2], &p[0]), is_top_left(&p[0], &p[1])];
+
+        let clamp = |v: f32, max: usize| (v.max(0.) as usize).min(max - 1);
+        let xmin = clamp(p[0].x.min(p[1].x).min(p[2].x).floor(), self.width);
+        let xmax = clamp(p[0].x.max(p[1].x).max(p[2].x).ceil(), self.width);
+        let ymin = clamp(p[0].y.min(p[1].y).min(p[2].y).floor(), self.height);
+        let ymax = clamp(p[0].y.max(p[1].y).max(p[2].y).ceil(), self.height);
 
-            for j in range(a.x as isize, b.x as isize) {
-                let phi = if b.x == a.x {
-                    1.
-                } else {
-                    (j - a.x as isize) as f32 / (b.x - a.x) as f32
-                };
-                let p = (a.to::<f32>() + (b - a).to::<f32>() * phi).to::<isize>();
-                if (*zbuf.val(p.x as usize, p.y as usize) as isize) < p.z {
-                    *zbuf.val_mut(p.x as usize, p.y as usize) = p.z as i32;
-                    try!(self.set(p.x as usize, p.y as usize, c));
+        for y in ymin..=ymax {
+            for x in xmin..=xmax {
+                let c = Vec3::new(x as f32 + 0.5, y as f32 + 0.5, 0.);
+                let e = [edge(&p[1], &p[2], &c), edge(&p[2], &p[0], &c), edge(&p[0], &p[1], &c)];
+                if !(0..3).all(|i| e[i] > 0. || (e[i] == 0. && top_left[i])) {
+                    continue;
+                }
+
+                // Screen-space barycentric coords, mapped back to the caller's vertex order
+                let mut bar = [0f32; 3];
+                for i in 0..3 {
+                    bar[order[i]] = e[i] / area;
+                }
+                let z = bar[0] * pts[0].z + bar[1] * pts[1].z + bar[2] * pts[2].z;
+                let depth = D::from_f32(z);
+                if !zbuf.test.passes(depth, *zbuf.val(x, y)) {
+                    continue;
+                }
+
+                // Attributes are linear in 1/w rather than in screen space
+                let mut persp = [bar[0] / w[0], bar[1] / w[1], bar[2] / w[2]];
+                let sum = persp[0] + persp[1] + persp[2];
+                for b in persp.iter_mut() {
+                    *b /= sum;
+                }
+                let v = V::interpolate(varyings, &Vec3::new(persp[0], persp[1], persp[2]));
+                if let Some(color) = shade(&v) {
+                    *zbuf.val_mut(x, y) = depth;
+                    self.set(x, y, &color)?;
                 }
             }
         }
 
         Ok(())
     }
+
+    fn draw_face<S: Shader, D: Depth>(&mut self, shader: &mut S, face: usize, cull: CullMode, zbuf: &mut ZBuffer<D>) -> Result<()> {
+        let corners = [shader.vertex(face, 0), shader.vertex(face, 1), shader.vertex(face, 2)];
+        let poly: Vec<(Vec4f, S::Varying)> = corners.into_iter().map(|v| (v.pos, v.varying)).collect();
+        let poly = clip(poly, self.width as f32, self.height as f32);
+        if poly.len() < 3 {
+            return Ok(())
+        }
+        let pts: Vec<Vec3f> = poly.iter().map(|v| v.0.project()).collect();
+        if is_culled(&pts, cull) {
+            return Ok(())
+        }
+
+        for i in 1..poly.len() - 1 {
+            self.rasterize(
+                &[pts[0], pts[i], pts[i + 1]],
+                &[poly[0].0.w, poly[i].0.w, poly[i + 1].0.w],
+                &[poly[0].1.clone(), poly[i].1.clone(), poly[i + 1].1.clone()],
+                zbuf,
+                |v| shader.fragment(v),
+                )?;
+        }
+        Ok(())
+    }
+}
+
+// Faces are counter-clockwise on screen (y up) when looked at from the front
+pub(crate) fn is_culled(pts: &[Vec3f], cull: CullMode) -> bool {
+    let area: f32 = (0..pts.len()).map(|i| {
+        let (a, b) = (&pts[i], &pts[(i + 1) % pts.len()]);
+        a.x * b.y - b.x * a.y
+    }).sum();
+    match cull {
+        CullMode::None => false,
+        CullMode::Back => area <= 0.,
+        CullMode::Front => area >= 0.,
+    }
+}
+
+// Points closer than this to the eye plane are clipped, so that w stays safely positive
+const NEAR_W: f32 = 1e-3;
+
+// Signed distances to the clipping planes: w > 0 and the viewport
+fn plane_distances(p: &Vec4f, width: f32, height: f32) -> [f32; 5] {
+    [p.w - NEAR_W, p.x, width * p.w - p.x, p.y, height * p.w - p.y]
+}
+
+// Part of a segment in front of the eye plane and inside the viewport, in homogeneous screen space
+pub(crate) fn clip_segment(mut from: Vec4f, mut to: Vec4f, width: f32, height: f32) -> Option<(Vec4f, Vec4f)> {
+    for k in 0..5 {
+        let (da, db) = (plane_distances(&from, width, height)[k], plane_distances(&to, width, height)[k]);
+        match (da >= 0., db >= 0.) {
+            (true, true) => {},
+            (false, false) => return None,
+            _ => {
+                let cut = from + (to - from) * (da / (da - db));
+                if da >= 0. { to = cut } else { from = cut }
+            },
+        }
+    }
+    Some((from, to))
+}
+
+// Face outline clipped against the eye plane and the viewport, empty when nothing is visible
+pub(crate) fn clip_face(pts: [Vec4f; 3], width: f32, height: f32) -> Vec<Vec4f> {
+    clip(pts.into_iter().map(|p| (p, ())).collect(), width, height).into_iter().map(|v| v.0).collect()
+}
+
+// Sutherland-Hodgman clipping of a polygon in homogeneous screen space against w > 0 and the viewport.
+// Varyings are linear along the edges in homogeneous space, so they are interpolated directly.
+fn clip<V: Interpolate>(mut poly: Vec<(Vec4f, V)>, width: f32, height: f32) -> Vec<(Vec4f, V)> {
+    for k in 0..5 {
+        let plane = |p: &Vec4f| plane_distances(p, width, height)[k];
+        if poly.iter().all(|v| plane(&v.0) >= 0.) {
+            continue;
+        }
+        let mut out = Vec::with_capacity(poly.len() + 1);
+        for i in 0..poly.len() {
+            let (a, b) = (&poly[i], &poly[(i + 1) % poly.len()]);
+            let (da, db) = (plane(&a.0), plane(&b.0));
+            if da >= 0. {
+                out.push(a.clone());
+            }
+            if (da >= 0.) != (db >= 0.) {
+                let t = da / (da - db);
+                let pos = a.0 + (b.0 - a.0) * t;
+                let varying = V::interpolate(&[a.1.clone(), b.1.clone(), b.1.clone()], &Vec3::new(1. - t, t, 0.));
+                out.push((pos, varying));
+            }
+        }
+        poly = out;
+        if poly.len() < 3 {
+            break;
+        }
+    }
+    poly
+}
+
+#[inline]
+fn edge(a: &Vec3f, b: &Vec3f, p: &Vec3f) -> f32 {
+    (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x)
+}
+
+#[inline]
+fn is_top_left(a: &Vec3f, b: &Vec3f) -> bool {
+    let dx = b.x - a.x;
+    let dy = b.y - a.y;
+    dy < 0. || (dy == 0. && dx < 0.)
+}
+
+pub trait Interpolate: Sized + Clone {
+    fn interpolate(v: &[Self; 3], bar: &Vec3f) -> Self;
+}
+
+impl Interpolate for () {
+    #[inline]
+    fn interpolate(_: &[(); 3], _: &Vec3f) {}
+}
+
+impl Interpolate for f32 {
+    #[inline]
+    fn interpolate(v: &[f32; 3], bar: &Vec3f) -> f32 {
+        v[0] * bar.x + v[1] * bar.y + v[2] * bar.z
+    }
+}
+
+impl Interpolate for Vec2f {
+    #[inline]
+    fn interpolate(v: &[Vec2f; 3], bar: &Vec3f) -> Vec2f {
+        v[0] * bar.x + v[1] * bar.y + v[2] * bar.z
+    }
+}
+
+impl Interpolate for Vec3f {
+    #[inline]
+    fn interpolate(v: &[Vec3f; 3], bar: &Vec3f) -> Vec3f {
+        v[0] * bar.x + v[1] * bar.y + v[2] * bar.z
+    }
+}
+
+impl Interpolate for Color {
+    fn interpolate(v: &[Color; 3], bar: &Vec3f) -> Color {
+        let mut c = v[0].clone();
+        for i in 0..4 {
+            let f = v[0][i] as f32 * bar.x + v[1][i] as f32 * bar.y + v[2][i] as f32 * bar.z;
+            c[i] = (f + 0.5).clamp(0., 255.) as u8;
+        }
+        c
+    }
+}
+
+impl<T: Interpolate, const N: usize> Interpolate for [T; N] {
+    fn interpolate(v: &[[T; N]; 3], bar: &Vec3f) -> [T; N] {
+        std::array::from_fn(|i| T::interpolate(&[v[0][i].clone(), v[1][i].clone(), v[2][i].clone()], bar))
+    }
 }
+
+macro_rules! impl_interpolate_tuple (
+    ($($t:ident : $i:tt),+) => (
+        impl<$($t: Interpolate),+> Interpolate for ($($t,)+) {
+            fn interpolate(v: &[Self; 3], bar: &Vec3f) -> Self {
+                ($($t::interpolate(&[v[0].$i.clone(), v[1].$i.clone(), v[2].$i.clone()], bar),)+)
+            }
+        }
+    )
+);
+
+impl_interpolate_tuple!(A: 0, B: 1);
+impl_interpolate_tuple!(A: 0, B: 1, C: 2);
+impl_interpolate_tuple!(A: 0, B: 1, C: 2, D: 3);
+impl_interpolate_tuple!(A: 0, B: 1, C: 2, D: 3, E: 4);
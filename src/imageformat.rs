@@ -0,0 +1,74 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use crate::error::Result;
+use crate::tgaimage::Image;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ImageFormat {
+    Tga {rle: bool, pixels: TgaPixels},
+    Png,
+    Ppm {ascii: bool},
+    Pgm {ascii: bool},
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum TgaPixels {
+    #[default]
+    TrueColor,
+    // 8-bit indices into a color map, for images with at most 256 colors
+    ColorMapped,
+    // 16-bit A1R5G5B5
+    HighColor,
+}
+
+impl ImageFormat {
+    // RLE TGA and binary PPM/PGM for the known extensions
+    pub fn from_extension(filename: &Path) -> Option<ImageFormat> {
+        let ext = filename.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "tga" => Some(ImageFormat::Tga {rle: true, pixels: TgaPixels::TrueColor}),
+            "png" => Some(ImageFormat::Png),
+            "ppm" | "pnm" => Some(ImageFormat::Ppm {ascii: false}),
+            "pgm" => Some(ImageFormat::Pgm {ascii: false}),
+            _ => None,
+        }
+    }
+}
+
+impl Image {
+    // PNG, PPM/PGM and BMP are recognised by their signature, anything else is read as TGA
+    pub fn read(f: &mut dyn Read) -> Result<Self> {
+        let mut data = Vec::new();
+        f.read_to_end(&mut data)?;
+        let mut input = data.as_slice();
+        match data.get(..2) {
+            Some(b"\x89P") => Image::read_png(&mut input),
+            Some(b"P2" | b"P3" | b"P5" | b"P6") => Image::read_pnm(&mut input),
+            Some(b"BM") => Image::read_bmp(&mut input),
+            _ => Image::read_tga(&mut input),
+        }
+    }
+
+    pub fn read_file<P: AsRef<Path>>(filename: P) -> Result<Self> {
+        Image::read(&mut BufReader::new(File::open(filename)?))
+    }
+
+    pub fn write(&self, f: &mut dyn Write, format: ImageFormat) -> Result<()> {
+        match format {
+            ImageFormat::Tga {rle, pixels: TgaPixels::TrueColor} => self.write_tga(f, rle),
+            ImageFormat::Tga {rle, pixels: TgaPixels::ColorMapped} => self.write_tga_mapped(f, rle),
+            ImageFormat::Tga {rle, pixels: TgaPixels::HighColor} => self.write_tga_16(f, rle),
+            ImageFormat::Png => self.write_png(f),
+            ImageFormat::Ppm {ascii} => self.write_ppm(f, ascii),
+            ImageFormat::Pgm {ascii} => self.write_pgm(f, ascii),
+        }
+    }
+
+    pub fn write_file<P: AsRef<Path>>(&self, filename: P, format: ImageFormat) -> Result<()> {
+        let mut f = BufWriter::new(File::create(filename)?);
+        self.write(&mut f, format)?;
+        f.flush()?;
+        Ok(())
+    }
+}
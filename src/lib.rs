@@ -0,0 +1,37 @@
+pub mod error;
+pub mod tgaimage;
+pub mod tgameta;
+pub mod bmp;
+pub mod geom;
+pub mod imageformat;
+pub mod vec;
+pub mod material;
+pub mod model;
+pub mod obj;
+pub mod ply;
+pub mod png;
+pub mod pnm;
+pub mod render;
+pub mod shader;
+pub mod shadow;
+pub mod ssao;
+pub mod stl;
+pub mod texture;
+pub mod triangulate;
+pub mod zbuffer;
+pub mod zlib;
+
+pub use error::{Error, ParseError, Result};
+pub use tgaimage::{Color, Image};
+pub use tgameta::{TgaExtension, TgaMetadata, TgaTag, TgaTimestamp};
+pub use material::Material;
+pub use model::{Corner, FaceInfo, MeshFormat, Model};
+pub use vec::{Mat4, Vec2, Vec2f, Vec2i, Vec3, Vec3f, Vec3i, Vec4, Vec4f};
+pub use geom::{CullMode, GeomActions};
+pub use imageformat::{ImageFormat, TgaPixels};
+pub use render::Renderer;
+pub use shader::{Shader, VertexOut};
+pub use shadow::{ShadowMap, ShadowOptions};
+pub use ssao::SsaoOptions;
+pub use texture::{Filter, NormalMap, Texture, Wrap};
+pub use zbuffer::{DepthTest, ZBuffer};
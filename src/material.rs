@@ -0,0 +1,120 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use crate::error::{Location, Result};
+use crate::texture::{NormalMap, Texture};
+use crate::vec::{Vec3, Vec3f};
+
+#[derive(Clone, Debug)]
+pub struct Material {
+    pub name: String,
+    pub ambient: Vec3f,
+    pub diffuse: Vec3f,
+    pub specular: Vec3f,
+    pub shininess: f32,
+    pub dissolve: f32,
+    pub diffuse_map: Option<PathBuf>,
+    pub bump_map: Option<PathBuf>,
+    pub specular_map: Option<PathBuf>,
+}
+
+// Maps of a material, read from its map paths when it gets rendered
+#[derive(Clone, Debug, Default)]
+pub struct MaterialTextures {
+    pub diffuse: Option<Texture>,
+    pub normal: Option<NormalMap>,
+    pub specular: Option<Texture>,
+}
+
+impl Material {
+    pub fn new(name: &str) -> Self {
+        Material {
+            name: name.to_string(),
+            ambient: Vec3::new(0., 0., 0.),
+            diffuse: Vec3::new(1., 1., 1.),
+            specular: Vec3::new(0., 0., 0.),
+            shininess: 0.,
+            dissolve: 1.,
+            diffuse_map: None,
+            bump_map: None,
+            specular_map: None,
+        }
+    }
+
+    // A map that is missing or cannot be decoded is left out, the material still renders
+    pub fn load_textures(&self) -> MaterialTextures {
+        let load = |path: &Option<PathBuf>| path.as_ref().and_then(|p| Texture::read_file(p).ok());
+        MaterialTextures {
+            diffuse: load(&self.diffuse_map),
+            normal: load(&self.bump_map).map(NormalMap::Tangent),
+            specular: load(&self.specular_map),
+        }
+    }
+}
+
+fn parse_color(loc: &Location, parts: &[&str]) -> Result<Vec3f> {
+    let r = loc.parse(parts.get(1), "a red component")?;
+    // A single value stands for a gray color
+    if parts.len() == 2 {
+        return Ok(Vec3::new(r, r, r));
+    }
+    Ok(Vec3::new(r, loc.parse(parts.get(2), "a green component")?, loc.parse(parts.get(3), "a blue component")?))
+}
+
+// Texture statements may carry options like -bm or -s before the file name, which always comes last
+fn map_path(loc: &Location, dir: &Path, parts: &[&str]) -> Result<PathBuf> {
+    match parts.last() {
+        Some(name) if parts.len() > 1 => Ok(dir.join(name)),
+        _ => Err(loc.error("", "a texture file name")),
+    }
+}
+
+// Texture paths are resolved relative to dir, filename only shows up in errors
+fn parse_mtl(input: &mut dyn BufRead, filename: Option<&Path>, dir: &Path) -> Result<Vec<Material>> {
+    let mut materials: Vec<Material> = Vec::new();
+
+    for (num, line) in input.lines().enumerate() {
+        let line = line?;
+        let loc = Location {file: filename, line: num + 1};
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.is_empty() || parts[0].starts_with('#') {
+            continue
+        }
+        if parts[0] == "newmtl" {
+            if parts.len() < 2 {
+                return Err(loc.error("", "a material name"));
+            }
+            materials.push(Material::new(&parts[1..].join(" ")));
+            continue
+        }
+        let mat = match materials.last_mut() {
+            Some(mat) => mat,
+            None => return Err(loc.error(parts[0], "newmtl before any material statement")),
+        };
+        match parts[0] {
+            "Ka" => mat.ambient = parse_color(&loc, &parts)?,
+            "Kd" => mat.diffuse = parse_color(&loc, &parts)?,
+            "Ks" => mat.specular = parse_color(&loc, &parts)?,
+            "Ns" => mat.shininess = loc.parse(parts.get(1), "a specular exponent")?,
+            "d" => mat.dissolve = loc.parse(parts.get(1), "a dissolve factor")?,
+            "Tr" => mat.dissolve = 1. - loc.parse::<f32>(parts.get(1), "a transparency factor")?,
+            "map_Kd" => mat.diffuse_map = Some(map_path(&loc, dir, &parts)?),
+            "map_Bump" | "map_bump" | "bump" => mat.bump_map = Some(map_path(&loc, dir, &parts)?),
+            "map_Ks" => mat.specular_map = Some(map_path(&loc, dir, &parts)?),
+            _ => {},
+        }
+    }
+    Ok(materials)
+}
+
+// Texture paths are resolved relative to the current directory
+pub fn read_mtl(input: &mut dyn BufRead) -> Result<Vec<Material>> {
+    parse_mtl(input, None, Path::new(""))
+}
+
+// Reads all materials of an MTL file, texture paths are resolved relative to the file
+pub fn read_mtl_file<P: AsRef<Path>>(filename: P) -> Result<Vec<Material>> {
+    let filename = filename.as_ref();
+    let dir = filename.parent().unwrap_or_else(|| Path::new(""));
+    parse_mtl(&mut BufReader::new(File::open(filename)?), Some(filename), dir)
+}
@@ -1,60 +1,147 @@
-use std::vec::Vec;
-use std::slice::SliceExt;
+use std::fs::File;
+use std::io::{BufRead, Read};
+use std::path::Path;
 use std::str::FromStr;
-use vec::{Vec3, Vec3f};
-use std::old_path::posix::Path;
-use std::old_io::{File, BufferedReader, IoResult, IoError, IoErrorKind};
+use crate::error::{Error, Result};
+use crate::material::Material;
+use crate::obj::{read_obj, read_obj_file};
+use crate::ply::{read_ply, read_ply_file};
+use crate::stl::{read_stl, read_stl_file};
+use crate::triangulate::triangulate;
+use crate::vec::{Vec2f, Vec3f};
 
-#[derive(Debug)]
-pub struct Model {
-    verts: Vec<Vec3f>,
-    faces: Vec<Vec<usize>>,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Corner {
+    pub vert: usize,
+    pub uv: Option<usize>,
+    pub norm: Option<usize>,
+}
+
+// State set by the o, g, s and usemtl statements preceding a face, indices point into
+// the model objects, groups and materials
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FaceInfo {
+    pub object: Option<usize>,
+    pub group: Option<usize>,
+    pub smoothing: Option<u32>,
+    pub material: Option<usize>,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum MeshFormat {
+    Obj,
+    Stl,
+    Ply,
 }
 
-macro_rules! try_parse (
-    ($f:expr) => (
-        try!(FromStr::from_str(($f).trim()).map_err(|_| IoError{kind: IoErrorKind::InvalidInput, desc: "Invalid file format", detail: None}))
-    )
-);
-
-macro_rules! try_parse_opt (
-    ($f:expr) => (
-        match $f {
-            None => return Err(IoError{kind: IoErrorKind::InvalidInput, desc: "Invalid file format", detail: None}),
-            Some(x) => try_parse!(x)
+impl MeshFormat {
+    pub fn from_extension(filename: &Path) -> Option<MeshFormat> {
+        let ext = filename.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "obj" => Some(MeshFormat::Obj),
+            "stl" => Some(MeshFormat::Stl),
+            "ply" => Some(MeshFormat::Ply),
+            _ => None,
+        }
+    }
+
+    // OBJ and ASCII STL are plain text, binary STL has an arbitrary header followed by raw numbers
+    pub fn sniff(head: &[u8]) -> MeshFormat {
+        if head.starts_with(b"ply") {
+            MeshFormat::Ply
+        } else if head.starts_with(b"solid") || head.iter().any(|&b| b < 0x20 && !b"\t\n\r\x0c".contains(&b)) {
+            MeshFormat::Stl
+        } else {
+            MeshFormat::Obj
+        }
+    }
+
+    pub fn detect(filename: &Path) -> Result<MeshFormat> {
+        if let Some(format) = MeshFormat::from_extension(filename) {
+            return Ok(format);
         }
-    )
-);
+        let mut head = Vec::new();
+        File::open(filename)?.take(512).read_to_end(&mut head)?;
+        Ok(MeshFormat::sniff(&head))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Model {
+    pub(crate) verts: Vec<Vec3f>,
+    pub(crate) uvs: Vec<Vec2f>,
+    pub(crate) norms: Vec<Vec3f>,
+    // Per vertex, in [0, 1], empty when the mesh has none
+    pub(crate) colors: Vec<Vec3f>,
+    pub(crate) faces: Vec<Vec<Corner>>,
+    pub(crate) infos: Vec<FaceInfo>,
+    pub(crate) objects: Vec<String>,
+    pub(crate) groups: Vec<String>,
+    pub(crate) materials: Vec<Material>,
+}
 
 impl Model {
-    pub fn new(filename: &str) -> IoResult<Model> {
-        let p = Path::new(filename);
-        let mut f = BufferedReader::new(File::open(&p));
-        let mut verts = Vec::new();
-        let mut faces = Vec::new();
-
-        for line in f.lines() {
-            let parts: Vec<&str> = line.as_ref().unwrap().split(' ').filter(|x| !x.is_empty()).collect();
-            if parts.len() == 0 {
-                continue
+    // The format is guessed from the extension, or from the first bytes when it is unknown
+    pub fn new<P: AsRef<Path>>(filename: P) -> Result<Model> {
+        let filename = filename.as_ref();
+        match MeshFormat::detect(filename)? {
+            MeshFormat::Obj => read_obj_file(filename),
+            MeshFormat::Stl => read_stl_file(filename),
+            MeshFormat::Ply => read_ply_file(filename),
+        }
+    }
+
+    // The format is sniffed from the first buffered bytes. OBJ material libraries are looked up
+    // relative to the current directory.
+    pub fn from_reader(input: &mut dyn BufRead) -> Result<Model> {
+        let format = MeshFormat::sniff(input.fill_buf()?);
+        match format {
+            MeshFormat::Obj => read_obj(input),
+            MeshFormat::Stl => read_stl(input),
+            MeshFormat::Ply => read_ply(input),
+        }
+    }
+
+    // Meshes without groups and materials, e.g. generated ones. Corners index the given lists,
+    // colors are per vertex or empty and faces get triangulated.
+    pub fn from_faces(verts: Vec<Vec3f>, uvs: Vec<Vec2f>, norms: Vec<Vec3f>, colors: Vec<Vec3f>, faces: Vec<Vec<Corner>>) -> Result<Model> {
+        if faces.iter().any(|f| f.len() < 3) {
+            return Err(Error::InvalidFormat("Face with less than three corners"));
+        }
+        let valid = |c: &Corner| c.vert < verts.len() && c.uv.is_none_or(|i| i < uvs.len()) && c.norm.is_none_or(|i| i < norms.len());
+        if !faces.iter().flatten().all(valid) {
+            return Err(Error::InvalidFormat("Face index out of range"));
+        }
+        if !colors.is_empty() && colors.len() != verts.len() {
+            return Err(Error::InvalidFormat("Vertex colors do not match the vertices"));
+        }
+        let infos = vec![FaceInfo::default(); faces.len()];
+        let mut model = Model {verts, uvs, norms, colors, faces, infos, ..Default::default()};
+        model.triangulate();
+        Ok(model)
+    }
+
+    // Splits quads and n-gons into triangles, the renderer only draws the first three corners of a face
+    pub fn triangulate(&mut self) {
+        if self.faces.iter().all(|f| f.len() == 3) {
+            return;
+        }
+        let mut faces = Vec::with_capacity(self.faces.len());
+        let mut infos = Vec::with_capacity(self.infos.len());
+        for (face, info) in self.faces.drain(..).zip(self.infos.drain(..)) {
+            if face.len() == 3 {
+                faces.push(face);
+                infos.push(info);
+                continue;
             }
-            if parts[0] == "v" && parts.len() > 3 {
-                let v = Vec3::new(
-                    try_parse!(parts[1]),
-                    try_parse!(parts[2]),
-                    try_parse!(parts[3]),
-                    );
-                verts.push(v);
-            } else if parts[0] == "f" && parts.len() > 3 {
-                let mut indices: Vec<usize> = Vec::new();
-                for part in parts[1..].iter() {
-                    let idx = part.split('/').next();
-                    indices.push(try_parse_opt!(idx) - 1);
-                }
-                faces.push(indices);
+            let pts: Vec<Vec3f> = face.iter().map(|c| self.verts[c.vert]).collect();
+            for [a, b, c] in triangulate(&pts) {
+                faces.push(vec![face[a], face[b], face[c]]);
+                infos.push(info);
             }
         }
-        Ok(Model {verts: verts, faces: faces})
+        self.faces = faces;
+        self.infos = infos;
     }
 
     #[inline]
@@ -68,7 +155,38 @@ impl Model {
     }
 
     #[inline]
-    pub fn face(&self, idx: usize) -> &Vec<usize> {
+    pub fn nuvs(&self) -> usize {
+        self.uvs.len()
+    }
+
+    #[inline]
+    pub fn nnorms(&self) -> usize {
+        self.norms.len()
+    }
+
+    // Positions, texture coordinates and normals can be changed in place, e.g. to transform the mesh
+    #[inline]
+    pub fn verts_mut(&mut self) -> &mut [Vec3f] {
+        &mut self.verts
+    }
+
+    #[inline]
+    pub fn uvs_mut(&mut self) -> &mut [Vec2f] {
+        &mut self.uvs
+    }
+
+    #[inline]
+    pub fn norms_mut(&mut self) -> &mut [Vec3f] {
+        &mut self.norms
+    }
+
+    #[inline]
+    pub fn has_colors(&self) -> bool {
+        !self.colors.is_empty()
+    }
+
+    #[inline]
+    pub fn face(&self, idx: usize) -> &[Corner] {
         &self.faces[idx]
     }
 
@@ -76,4 +194,58 @@ impl Model {
     pub fn vert(&self, idx: usize) -> &Vec3f {
         &self.verts[idx]
     }
+
+    #[inline]
+    pub fn face_vert(&self, face: usize, corner: usize) -> &Vec3f {
+        &self.verts[self.faces[face][corner].vert]
+    }
+
+    #[inline]
+    pub fn uv(&self, face: usize, corner: usize) -> Option<Vec2f> {
+        self.faces[face][corner].uv.map(|i| self.uvs[i])
+    }
+
+    #[inline]
+    pub fn normal(&self, face: usize, corner: usize) -> Option<Vec3f> {
+        self.faces[face][corner].norm.map(|i| self.norms[i])
+    }
+
+    #[inline]
+    pub fn color(&self, face: usize, corner: usize) -> Option<Vec3f> {
+        self.colors.get(self.faces[face][corner].vert).copied()
+    }
+
+    #[inline]
+    pub fn face_info(&self, face: usize) -> &FaceInfo {
+        &self.infos[face]
+    }
+
+    #[inline]
+    pub fn objects(&self) -> &[String] {
+        &self.objects
+    }
+
+    #[inline]
+    pub fn groups(&self) -> &[String] {
+        &self.groups
+    }
+
+    #[inline]
+    pub fn materials(&self) -> &[Material] {
+        &self.materials
+    }
+
+    #[inline]
+    pub fn material(&self, face: usize) -> Option<&Material> {
+        self.infos[face].material.map(|i| &self.materials[i])
+    }
+}
+
+// Text meshes (OBJ, ASCII STL or PLY) held in memory
+impl FromStr for Model {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Model> {
+        Model::from_reader(&mut s.as_bytes())
+    }
 }
@@ -0,0 +1,353 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use crate::error::{Error, Location, Result};
+use crate::material::{read_mtl_file, Material};
+use crate::model::{Corner, FaceInfo, Model};
+use crate::vec::{Vec2, Vec3};
+
+// OBJ indices are 1-based, negative ones are relative to the end of the list read so far
+fn resolve_index(loc: &Location, corner: &str, s: &str, count: usize, what: &str) -> Result<usize> {
+    let idx: i64 = loc.parse(Some(&s), &format!("a {} index", what))?;
+    if idx > 0 && idx as usize <= count {
+        Ok(idx as usize - 1)
+    } else if idx < 0 && idx.unsigned_abs() <= count as u64 {
+        Ok(count - idx.unsigned_abs() as usize)
+    } else if count == 0 {
+        Err(loc.error(corner, format!("a {} defined before this face", what)))
+    } else {
+        Err(loc.error(corner, format!("a {} index between 1 and {} or -{} and -1", what, count, count)))
+    }
+}
+
+fn name_index(names: &mut Vec<String>, name: String) -> usize {
+    match names.iter().position(|n| *n == name) {
+        Some(idx) => idx,
+        None => {
+            names.push(name);
+            names.len() - 1
+        },
+    }
+}
+
+// Materials referenced by usemtl before their library is read get replaced once it is
+fn add_materials(materials: &mut Vec<Material>, loaded: Vec<Material>) {
+    for mat in loaded {
+        match materials.iter().position(|m| m.name == mat.name) {
+            Some(idx) => materials[idx] = mat,
+            None => materials.push(mat),
+        }
+    }
+}
+
+// Material libraries are looked up in dir, filename only shows up in errors
+fn parse_obj(input: &mut dyn BufRead, filename: Option<&Path>, dir: &Path) -> Result<Model> {
+    let mut verts = Vec::new();
+    let mut uvs = Vec::new();
+    let mut norms = Vec::new();
+    let mut faces = Vec::new();
+    let mut infos = Vec::new();
+    let mut objects = Vec::new();
+    let mut groups = Vec::new();
+    let mut materials = Vec::new();
+    let mut info = FaceInfo::default();
+
+    for (num, line) in input.lines().enumerate() {
+        let line = line?;
+        let loc = Location {file: filename, line: num + 1};
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.is_empty() || parts[0].starts_with('#') {
+            continue
+        }
+        match parts[0] {
+            "v" | "vn" => {
+                let v = Vec3::new(
+                    loc.parse(parts.get(1), "an x coordinate")?,
+                    loc.parse(parts.get(2), "a y coordinate")?,
+                    loc.parse(parts.get(3), "a z coordinate")?,
+                    );
+                if parts[0] == "v" { verts.push(v) } else { norms.push(v) }
+            },
+            "vt" => {
+                let uv = Vec2::new(
+                    loc.parse(parts.get(1), "a u coordinate")?,
+                    loc.parse(parts.get(2), "a v coordinate")?,
+                    );
+                uvs.push(uv);
+            },
+            "f" => {
+                let mut corners: Vec<Corner> = Vec::new();
+                for part in parts[1..].iter() {
+                    // v, v/vt, v//vn or v/vt/vn
+                    let mut idx = part.split('/');
+                    let vert = resolve_index(&loc, part, idx.next().unwrap_or(""), verts.len(), "vertex")?;
+                    let uv = match idx.next() {
+                        None | Some("") => None,
+                        Some(s) => Some(resolve_index(&loc, part, s, uvs.len(), "texture coordinate")?),
+                    };
+                    let norm = match idx.next() {
+                        None | Some("") => None,
+                        Some(s) => Some(resolve_index(&loc, part, s, norms.len(), "normal")?),
+                    };
+                    if idx.next().is_some() {
+                        return Err(loc.error(part, "a corner in v, v/vt, v//vn or v/vt/vn form"));
+                    }
+                    corners.push(Corner {vert, uv, norm});
+                }
+                if corners.len() < 3 {
+                    return Err(loc.error(parts.get(corners.len() + 1).unwrap_or(&""), "at least three face corners"));
+                }
+                faces.push(corners);
+                infos.push(info);
+            },
+            // A bare o, g or usemtl takes the following faces out of any object, group or material
+            "o" => info.object = if parts.len() > 1 { Some(name_index(&mut objects, parts[1..].join(" "))) } else { None },
+            // A face may belong to several groups, they are kept as a single name
+            "g" => info.group = if parts.len() > 1 { Some(name_index(&mut groups, parts[1..].join(" "))) } else { None },
+            "s" => {
+                info.smoothing = match parts.get(1) {
+                    Some(&"off") | Some(&"0") => None,
+                    s => Some(loc.parse(s, "a smoothing group number or off")?),
+                };
+            },
+            "usemtl" => {
+                let name = parts[1..].join(" ");
+                if name.is_empty() {
+                    info.material = None;
+                    continue;
+                }
+                let idx = match materials.iter().position(|m: &Material| m.name == name) {
+                    Some(idx) => idx,
+                    None => {
+                        materials.push(Material::new(&name));
+                        materials.len() - 1
+                    },
+                };
+                info.material = Some(idx);
+            },
+            "mtllib" => {
+                // Materials of a library that cannot be opened keep their defaults, errors inside
+                // one that can are still reported
+                for lib in parts[1..].iter() {
+                    match read_mtl_file(dir.join(lib)) {
+                        Ok(loaded) => add_materials(&mut materials, loaded),
+                        Err(Error::Io(_)) => {},
+                        Err(e) => return Err(e),
+                    }
+                }
+            },
+            // Lines, points, curves and surfaces are not rendered
+            _ => {},
+        }
+    }
+    let mut model = Model {verts, uvs, norms, faces, infos, objects, groups, materials, ..Default::default()};
+    model.triangulate();
+    Ok(model)
+}
+
+// Material libraries are looked up relative to the current directory
+pub fn read_obj(input: &mut dyn BufRead) -> Result<Model> {
+    parse_obj(input, None, Path::new(""))
+}
+
+pub fn read_obj_file<P: AsRef<Path>>(filename: P) -> Result<Model> {
+    let filename = filename.as_ref();
+    let dir = filename.parent().unwrap_or_else(|| Path::new(""));
+    parse_obj(&mut BufReader::new(File::open(filename)?), Some(filename), dir)
+}
+
+fn write_corner(f: &mut dyn Write, c: &Corner) -> Result<()> {
+    write!(f, " {}", c.vert + 1)?;
+    match (c.uv, c.norm) {
+        (None, None) => {},
+        (Some(uv), None) => write!(f, "/{}", uv + 1)?,
+        (None, Some(n)) => write!(f, "//{}", n + 1)?,
+        (Some(uv), Some(n)) => write!(f, "/{}/{}", uv + 1, n + 1)?,
+    }
+    Ok(())
+}
+
+// Objects, groups, smoothing groups and material names are written whenever they change between faces.
+// Materials are only referenced by name, no library gets written.
+pub fn write_obj(model: &Model, f: &mut dyn Write) -> Result<()> {
+    writeln!(f, "# {} vertices, {} texture coordinates, {} normals, {} faces",
+             model.nverts(), model.nuvs(), model.nnorms(), model.nfaces())?;
+    // f32 Display prints the shortest representation that reads back to the same value
+    for v in model.verts.iter() {
+        writeln!(f, "v {} {} {}", v.x, v.y, v.z)?;
+    }
+    for uv in model.uvs.iter() {
+        writeln!(f, "vt {} {}", uv.x, uv.y)?;
+    }
+    for n in model.norms.iter() {
+        writeln!(f, "vn {} {} {}", n.x, n.y, n.z)?;
+    }
+
+    let mut last = FaceInfo::default();
+    for (face, info) in model.faces.iter().zip(model.infos.iter()) {
+        if info.object != last.object {
+            match info.object {
+                Some(idx) => writeln!(f, "o {}", model.objects[idx])?,
+                None => writeln!(f, "o")?,
+            }
+        }
+        if info.group != last.group {
+            match info.group {
+                Some(idx) => writeln!(f, "g {}", model.groups[idx])?,
+                None => writeln!(f, "g")?,
+            }
+        }
+        if info.smoothing != last.smoothing {
+            match info.smoothing {
+                Some(s) => writeln!(f, "s {}", s)?,
+                None => writeln!(f, "s off")?,
+            }
+        }
+        if info.material != last.material {
+            match info.material {
+                Some(idx) => writeln!(f, "usemtl {}", model.materials[idx].name)?,
+                None => writeln!(f, "usemtl")?,
+            }
+        }
+        last = *info;
+
+        write!(f, "f")?;
+        for c in face.iter() {
+            write_corner(f, c)?;
+        }
+        writeln!(f)?;
+    }
+    Ok(())
+}
+
+pub fn write_obj_file<P: AsRef<Path>>(model: &Model, filename: P) -> Result<()> {
+    let mut f = BufWriter::new(File::create(filename)?);
+    write_obj(model, &mut f)?;
+    f.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_obj, write_obj_file};
+    use crate::error::Error;
+    use crate::model::{Corner, FaceInfo, Model};
+    use crate::vec::{Vec2, Vec3};
+
+    const TRIANGLE: &str = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 0 1\nvn 0 0 1\n";
+
+    fn corners(model: &Model, face: usize) -> Vec<(usize, Option<usize>, Option<usize>)> {
+        model.face(face).iter().map(|c| (c.vert, c.uv, c.norm)).collect()
+    }
+
+    fn parse_error_line(text: &str) -> usize {
+        match text.parse::<Model>() {
+            Err(Error::Parse(e)) => e.line,
+            Err(e) => panic!("expected a parse error, got {}", e),
+            Ok(_) => panic!("expected a parse error"),
+        }
+    }
+
+    #[test]
+    fn face_forms() {
+        let text = format!("{}f 1 2 3\nf 1/1 2/2 3/3\nf 1//1 2//1 3//1\nf 1/1/1 2/2/1 3/3/1\n", TRIANGLE);
+        let model: Model = text.parse().unwrap();
+        assert_eq!(model.nfaces(), 4);
+        assert_eq!(corners(&model, 0), [(0, None, None), (1, None, None), (2, None, None)]);
+        assert_eq!(corners(&model, 1), [(0, Some(0), None), (1, Some(1), None), (2, Some(2), None)]);
+        assert_eq!(corners(&model, 2), [(0, None, Some(0)), (1, None, Some(0)), (2, None, Some(0))]);
+        assert_eq!(corners(&model, 3), [(0, Some(0), Some(0)), (1, Some(1), Some(0)), (2, Some(2), Some(0))]);
+        assert_eq!(model.face_vert(3, 1), &Vec3::new(1., 0., 0.));
+        assert_eq!(model.normal(3, 2), Some(Vec3::new(0., 0., 1.)));
+    }
+
+    #[test]
+    fn negative_indices() {
+        // Relative to the vertices read so far, not to the whole file
+        let text = format!("{}f -3/-3/-1 -2/-2/-1 -1/-1/-1\nv 5 5 5\nf -4 -3 -1\n", TRIANGLE);
+        let model: Model = text.parse().unwrap();
+        assert_eq!(corners(&model, 0), [(0, Some(0), Some(0)), (1, Some(1), Some(0)), (2, Some(2), Some(0))]);
+        assert_eq!(model.face(1), [
+            Corner {vert: 0, uv: None, norm: None},
+            Corner {vert: 1, uv: None, norm: None},
+            Corner {vert: 3, uv: None, norm: None},
+        ]);
+    }
+
+    #[test]
+    fn quads_are_triangulated() {
+        let model: Model = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\ng quad\nf 1 2 3 4\n".parse().unwrap();
+        assert_eq!(model.nfaces(), 2);
+        assert_eq!(model.face_info(1).group, Some(0));
+    }
+
+    #[test]
+    fn invalid_faces() {
+        assert_eq!(parse_error_line(&format!("{}f 1 2 4\n", TRIANGLE)), 8);
+        assert_eq!(parse_error_line(&format!("{}f 1 2 -4\n", TRIANGLE)), 8);
+        assert_eq!(parse_error_line(&format!("{}f 1 2 -9223372036854775808\n", TRIANGLE)), 8);
+        assert_eq!(parse_error_line(&format!("{}f 0 1 2\n", TRIANGLE)), 8);
+        assert_eq!(parse_error_line(&format!("{}f 1 2\n", TRIANGLE)), 8);
+        assert_eq!(parse_error_line(&format!("{}f 1/1/1/1 2 3\n", TRIANGLE)), 8);
+        assert_eq!(parse_error_line(&format!("{}f 1/4 2 3\n", TRIANGLE)), 8);
+        assert_eq!(parse_error_line("f 1 2 3\n"), 1);
+    }
+
+    #[test]
+    fn write_read_round_trip() {
+        let text = format!("{}v 0.1 -2.5e-7 3.3333333\nvt 0.25 0.75\nvn 0.6 0.8 0\n\
+                            o body\ng head\ns 1\nusemtl skin\nf 1/1/1 2/2/1 3/3/1\nf 2//2 3//2 4//2\n\
+                            g eyes left\ns off\nusemtl iris\nf 1/4 3/4 4/4\n\
+                            g\nusemtl skin\nf -3 -2 -1 1\no\nusemtl\nf 1 2 3\n", TRIANGLE);
+        let model: Model = text.parse().unwrap();
+        let mut out = Vec::new();
+        write_obj(&model, &mut out).unwrap();
+        let copy: Model = String::from_utf8(out).unwrap().parse().unwrap();
+
+        assert_eq!(copy.verts, model.verts);
+        assert_eq!(copy.uvs, model.uvs);
+        assert_eq!(copy.norms, model.norms);
+        assert_eq!(copy.faces, model.faces);
+        assert_eq!(copy.infos, model.infos);
+        assert_eq!(copy.objects, ["body"]);
+        assert_eq!(copy.groups, ["head", "eyes left"]);
+        let names: Vec<&str> = copy.materials.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["skin", "iris"]);
+        assert_eq!(copy.infos[2], FaceInfo {object: Some(0), group: Some(1), smoothing: None, material: Some(1)});
+        assert_eq!(copy.infos[3], FaceInfo {object: Some(0), group: None, smoothing: None, material: Some(0)});
+        assert_eq!(copy.infos[5], FaceInfo::default());
+    }
+
+    #[test]
+    fn generated_mesh_file_round_trip() {
+        let corner = |vert, uv| Corner {vert, uv: Some(uv), norm: None};
+        let verts = vec![Vec3::new(0., 0., 0.), Vec3::new(1., 0., 0.), Vec3::new(1., 1., 0.), Vec3::new(0., 1., 0.)];
+        let uvs = vec![Vec2::new(0., 0.), Vec2::new(1., 1.)];
+        let faces = vec![vec![corner(0, 0), corner(1, 1), corner(2, 1), corner(3, 0)]];
+        let mut model = Model::from_faces(verts, uvs, Vec::new(), Vec::new(), faces).unwrap();
+        for v in model.verts_mut() {
+            *v = *v * 2. + Vec3::new(0., 0., -1.);
+        }
+
+        let path = std::env::temp_dir().join(format!("obj-round-trip-{}.obj", std::process::id()));
+        write_obj_file(&model, &path).unwrap();
+        let copy = Model::new(&path);
+        std::fs::remove_file(&path).unwrap();
+        let copy = copy.unwrap();
+
+        assert_eq!(copy.nfaces(), 2);
+        assert_eq!(copy.verts, model.verts);
+        assert_eq!(copy.uvs, model.uvs);
+        assert_eq!(copy.faces, model.faces);
+        assert_eq!(copy.vert(2), &Vec3::new(2., 2., -1.));
+    }
+
+    #[test]
+    fn invalid_generated_mesh() {
+        let verts = vec![Vec3::new(0., 0., 0.), Vec3::new(1., 0., 0.), Vec3::new(0., 1., 0.)];
+        let face = |verts: [usize; 3]| vec![verts.map(|vert| Corner {vert, uv: None, norm: None}).to_vec()];
+        assert!(Model::from_faces(verts.clone(), Vec::new(), Vec::new(), Vec::new(), face([0, 1, 3])).is_err());
+        assert!(Model::from_faces(verts.clone(), Vec::new(), Vec::new(), vec![Vec3::new(1., 1., 1.)], face([0, 1, 2])).is_err());
+        assert!(Model::from_faces(verts, Vec::new(), Vec::new(), Vec::new(), face([0, 1, 2])).is_ok());
+    }
+}
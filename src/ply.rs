@@ -0,0 +1,409 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::str::SplitAsciiWhitespace;
+use crate::error::{Error, Location, Result};
+use crate::model::{Corner, Model};
+use crate::vec::{Vec2, Vec3};
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+enum Encoding {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+enum PropType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl PropType {
+    fn from_name(name: &str) -> Option<PropType> {
+        match name {
+            "char" | "int8" => Some(PropType::I8),
+            "uchar" | "uint8" => Some(PropType::U8),
+            "short" | "int16" => Some(PropType::I16),
+            "ushort" | "uint16" => Some(PropType::U16),
+            "int" | "int32" => Some(PropType::I32),
+            "uint" | "uint32" => Some(PropType::U32),
+            "float" | "float32" => Some(PropType::F32),
+            "double" | "float64" => Some(PropType::F64),
+            _ => None,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            PropType::I8 | PropType::U8 => 1,
+            PropType::I16 | PropType::U16 => 2,
+            PropType::I32 | PropType::U32 | PropType::F32 => 4,
+            PropType::F64 => 8,
+        }
+    }
+
+    fn is_integer(self) -> bool {
+        !matches!(self, PropType::F32 | PropType::F64)
+    }
+
+    // Integer color channels span their whole range, float ones are already in [0, 1]
+    fn unit(self, v: f64) -> f32 {
+        let max = match self {
+            PropType::I8 => i8::MAX as f64,
+            PropType::U8 => u8::MAX as f64,
+            PropType::I16 => i16::MAX as f64,
+            PropType::U16 => u16::MAX as f64,
+            PropType::I32 => i32::MAX as f64,
+            PropType::U32 => u32::MAX as f64,
+            PropType::F32 | PropType::F64 => 1.,
+        };
+        (v / max) as f32
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Property {
+    Scalar(String, PropType),
+    // Count type, item type
+    List(String, PropType, PropType),
+}
+
+impl Property {
+    fn name(&self) -> &str {
+        match *self {
+            Property::Scalar(ref name, _) | Property::List(ref name, _, _) => name,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    props: Vec<Property>,
+}
+
+impl Element {
+    fn find(&self, names: &[&str]) -> Option<usize> {
+        self.props.iter().position(|p| names.contains(&p.name()))
+    }
+}
+
+struct Header {
+    encoding: Encoding,
+    elements: Vec<Element>,
+}
+
+fn read_header(input: &mut dyn BufRead, filename: Option<&Path>) -> Result<Header> {
+    let mut encoding = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut line = String::new();
+    let mut num = 0;
+
+    loop {
+        line.clear();
+        num += 1;
+        let loc = Location {file: filename, line: num};
+        if input.read_line(&mut line)? == 0 {
+            return Err(loc.error("", "end_header"));
+        }
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if num == 1 {
+            if parts.first() != Some(&"ply") {
+                return Err(loc.error(parts.first().unwrap_or(&""), "the ply magic number"));
+            }
+            continue;
+        }
+        match parts.first() {
+            None | Some(&"comment") | Some(&"obj_info") => {},
+            Some(&"format") => {
+                encoding = Some(match parts.get(1) {
+                    Some(&"ascii") => Encoding::Ascii,
+                    Some(&"binary_little_endian") => Encoding::BinaryLittleEndian,
+                    Some(&"binary_big_endian") => Encoding::BinaryBigEndian,
+                    other => return Err(loc.error(other.unwrap_or(&""),
+                                                  "ascii, binary_little_endian or binary_big_endian")),
+                });
+            },
+            Some(&"element") => {
+                let name = parts.get(1).ok_or_else(|| loc.error("", "an element name"))?.to_string();
+                let count = loc.parse(parts.get(2), "an element count")?;
+                elements.push(Element {name, count, props: Vec::new()});
+            },
+            Some(&"property") => {
+                let element = elements.last_mut().ok_or_else(|| loc.error("property", "an element before its properties"))?;
+                let prop_type = |idx: usize| {
+                    let name = parts.get(idx).unwrap_or(&"");
+                    PropType::from_name(name).ok_or_else(|| loc.error(name, "a property type"))
+                };
+                let prop = if parts.get(1) == Some(&"list") {
+                    let name = parts.get(4).ok_or_else(|| loc.error("", "a property name"))?;
+                    Property::List(name.to_string(), prop_type(2)?, prop_type(3)?)
+                } else {
+                    let name = parts.get(2).ok_or_else(|| loc.error("", "a property name"))?;
+                    Property::Scalar(name.to_string(), prop_type(1)?)
+                };
+                element.props.push(prop);
+            },
+            Some(&"end_header") => break,
+            Some(other) => return Err(loc.error(other, "a header keyword")),
+        }
+    }
+    let encoding = encoding.ok_or(Error::InvalidFormat("PLY header has no format line"))?;
+    Ok(Header {encoding, elements})
+}
+
+enum Body<'a> {
+    Ascii(SplitAsciiWhitespace<'a>),
+    Binary {data: &'a [u8], pos: usize, big_endian: bool},
+}
+
+macro_rules! read_binary (
+    ($data:expr, $big_endian:expr, $t:ty) => ({
+        let bytes = $data.try_into().unwrap();
+        (if $big_endian { <$t>::from_be_bytes(bytes) } else { <$t>::from_le_bytes(bytes) }) as f64
+    })
+);
+
+impl Body<'_> {
+    fn value(&mut self, t: PropType) -> Result<f64> {
+        match *self {
+            Body::Ascii(ref mut tokens) => {
+                let token = tokens.next().ok_or(Error::InvalidFormat("Truncated PLY data"))?;
+                token.parse().map_err(|_| Error::InvalidFormat("Invalid PLY value"))
+            },
+            Body::Binary {data, ref mut pos, big_endian} => {
+                let bytes = data.get(*pos..*pos + t.size()).ok_or(Error::InvalidFormat("Truncated PLY data"))?;
+                *pos += t.size();
+                Ok(match t {
+                    PropType::I8 => bytes[0] as i8 as f64,
+                    PropType::U8 => bytes[0] as f64,
+                    PropType::I16 => read_binary!(bytes, big_endian, i16),
+                    PropType::U16 => read_binary!(bytes, big_endian, u16),
+                    PropType::I32 => read_binary!(bytes, big_endian, i32),
+                    PropType::U32 => read_binary!(bytes, big_endian, u32),
+                    PropType::F32 => read_binary!(bytes, big_endian, f32),
+                    PropType::F64 => read_binary!(bytes, big_endian, f64),
+                })
+            },
+        }
+    }
+
+    // Scalars come back as a single value
+    fn property(&mut self, prop: &Property) -> Result<Vec<f64>> {
+        match *prop {
+            Property::Scalar(_, t) => Ok(vec![self.value(t)?]),
+            Property::List(_, count_type, item_type) => {
+                let count = self.value(count_type)? as usize;
+                (0..count).map(|_| self.value(item_type)).collect()
+            },
+        }
+    }
+}
+
+// Vertices keep their normals, colors and texture coordinates, faces can be any polygon
+fn parse_ply(input: &mut dyn BufRead, filename: Option<&Path>) -> Result<Model> {
+    let header = read_header(input, filename)?;
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+    let mut body = match header.encoding {
+        Encoding::Ascii => Body::Ascii(std::str::from_utf8(&data)
+                                       .map_err(|_| Error::InvalidFormat("PLY text is not valid UTF-8"))?
+                                       .split_ascii_whitespace()),
+        encoding => Body::Binary {data: &data, pos: 0, big_endian: encoding == Encoding::BinaryBigEndian},
+    };
+
+    let mut verts = Vec::new();
+    let mut uvs = Vec::new();
+    let mut norms = Vec::new();
+    let mut colors = Vec::new();
+    let mut polygons: Vec<Vec<usize>> = Vec::new();
+
+    for element in header.elements.iter() {
+        let pos = [element.find(&["x"]), element.find(&["y"]), element.find(&["z"])];
+        let normal = [element.find(&["nx", "normal_x"]), element.find(&["ny", "normal_y"]), element.find(&["nz", "normal_z"])];
+        let color = [
+            element.find(&["red", "diffuse_red", "r"]),
+            element.find(&["green", "diffuse_green", "g"]),
+            element.find(&["blue", "diffuse_blue", "b"]),
+        ];
+        let uv = [element.find(&["s", "u", "texture_u", "texture_s"]), element.find(&["t", "v", "texture_v", "texture_t"])];
+        let indices = element.find(&["vertex_indices", "vertex_index"]);
+        if element.name == "vertex" && pos.contains(&None) {
+            return Err(Error::InvalidFormat("PLY vertices need x, y and z properties"));
+        }
+        if element.name == "face" {
+            match indices.map(|i| &element.props[i]) {
+                None => return Err(Error::InvalidFormat("PLY faces need a vertex_indices property")),
+                Some(Property::Scalar(_, t)) | Some(Property::List(_, _, t)) if !t.is_integer() => {
+                    return Err(Error::InvalidFormat("PLY vertex indices must have an integer type"));
+                },
+                _ => {},
+            }
+        }
+
+        for _ in 0..element.count {
+            let mut values = Vec::with_capacity(element.props.len());
+            for prop in element.props.iter() {
+                values.push(body.property(prop)?);
+            }
+            // Lists where a scalar is expected read as their first item
+            let get = |i: usize| values[i].first().copied().unwrap_or(0.);
+            if element.name == "vertex" {
+                if let [Some(x), Some(y), Some(z)] = pos {
+                    verts.push(Vec3::new(get(x) as f32, get(y) as f32, get(z) as f32));
+                }
+                if let [Some(x), Some(y), Some(z)] = normal {
+                    norms.push(Vec3::new(get(x) as f32, get(y) as f32, get(z) as f32));
+                }
+                if let [Some(r), Some(g), Some(b)] = color {
+                    let unit = |i: usize| match element.props[i] {
+                        Property::Scalar(_, t) | Property::List(_, _, t) => t.unit(get(i)),
+                    };
+                    colors.push(Vec3::new(unit(r), unit(g), unit(b)));
+                }
+                if let [Some(u), Some(v)] = uv {
+                    uvs.push(Vec2::new(get(u) as f32, get(v) as f32));
+                }
+            } else if element.name == "face" {
+                let polygon = &values[indices.unwrap_or(0)];
+                // ASCII values parse as any number, fract() is also NaN for NaN and infinities
+                if polygon.iter().any(|&v| v.fract() != 0.) {
+                    return Err(Error::InvalidFormat("PLY face index is not an integer"));
+                }
+                if polygon.iter().any(|&v| v < 0.) {
+                    return Err(Error::InvalidFormat("PLY face index out of range"));
+                }
+                polygons.push(polygon.iter().map(|&v| v as usize).collect());
+            }
+        }
+    }
+
+    let mut faces = Vec::with_capacity(polygons.len());
+    for polygon in polygons {
+        if polygon.len() < 3 {
+            return Err(Error::InvalidFormat("PLY face with less than three vertices"));
+        }
+        if polygon.iter().any(|&v| v >= verts.len()) {
+            return Err(Error::InvalidFormat("PLY face index out of range"));
+        }
+        let corner = |v: usize| Corner {
+            vert: v,
+            uv: if uvs.is_empty() { None } else { Some(v) },
+            norm: if norms.is_empty() { None } else { Some(v) },
+        };
+        faces.push(polygon.into_iter().map(corner).collect());
+    }
+    Model::from_faces(verts, uvs, norms, colors, faces)
+}
+
+pub fn read_ply(input: &mut dyn BufRead) -> Result<Model> {
+    parse_ply(input, None)
+}
+
+pub fn read_ply_file<P: AsRef<Path>>(filename: P) -> Result<Model> {
+    let filename = filename.as_ref();
+    parse_ply(&mut BufReader::new(File::open(filename)?), Some(filename))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::read_ply;
+    use crate::model::Model;
+    use crate::vec::Vec3;
+
+    const HEADER: &str = "element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+";
+
+    const VERTS: [([f32; 3], [u8; 3]); 4] = [
+        ([0., 0., 0.], [255, 0, 0]),
+        ([1., 0., 0.], [0, 255, 0]),
+        ([1., 1., 0.], [0, 0, 255]),
+        ([0., 1., 0.], [255, 255, 255]),
+    ];
+
+    fn binary(format: &str, big_endian: bool) -> Vec<u8> {
+        let mut data = format!("ply\nformat {} 1.0\n{}", format, HEADER).into_bytes();
+        for (pos, color) in VERTS {
+            for v in pos {
+                data.extend(if big_endian { v.to_be_bytes() } else { v.to_le_bytes() });
+            }
+            data.extend(color);
+        }
+        data.push(4);
+        for i in 0..4i32 {
+            data.extend(if big_endian { i.to_be_bytes() } else { i.to_le_bytes() });
+        }
+        data
+    }
+
+    // The quad is split in two, colors are scaled to [0, 1]
+    fn check_quad(model: &Model) {
+        assert_eq!(model.nverts(), 4);
+        assert_eq!(model.nfaces(), 2);
+        assert!(model.has_colors());
+        assert_eq!(model.vert(2), &Vec3::new(1., 1., 0.));
+        for face in 0..2 {
+            for corner in 0..3 {
+                let [r, g, b] = VERTS[model.face(face)[corner].vert].1.map(|c| c as f32 / 255.);
+                assert_eq!(model.color(face, corner), Some(Vec3::new(r, g, b)));
+            }
+        }
+    }
+
+    #[test]
+    fn ascii() {
+        let text = format!("ply\nformat ascii 1.0\ncomment test\n{}\
+                            0 0 0 255 0 0\n1 0 0 0 255 0\n1 1 0 0 0 255\n0 1 0 255 255 255\n4 0 1 2 3\n", HEADER);
+        check_quad(&text.parse().unwrap());
+    }
+
+    #[test]
+    fn binary_little_endian() {
+        let data = binary("binary_little_endian", false);
+        check_quad(&read_ply(&mut &data[..]).unwrap());
+        check_quad(&Model::from_reader(&mut &data[..]).unwrap());
+    }
+
+    #[test]
+    fn binary_big_endian() {
+        let data = binary("binary_big_endian", true);
+        check_quad(&read_ply(&mut &data[..]).unwrap());
+    }
+
+    #[test]
+    fn truncated_binary() {
+        let data = binary("binary_little_endian", false);
+        assert!(read_ply(&mut &data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn invalid_face_indices() {
+        let text = |indices: &str, item_type: &str| format!(
+            "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+             element face 1\nproperty list uchar {} vertex_indices\nend_header\n0 0 0\n1 0 0\n0 1 0\n3 {}\n",
+            item_type, indices);
+        assert!(text("0 1 2", "int").parse::<Model>().is_ok());
+        assert!(text("0 1 2", "float").parse::<Model>().is_err());
+        assert!(text("0 1.5 2", "int").parse::<Model>().is_err());
+        assert!(text("0 nan 2", "int").parse::<Model>().is_err());
+        assert!(text("0 -1 2", "int").parse::<Model>().is_err());
+        assert!(text("0 1 3", "int").parse::<Model>().is_err());
+    }
+}
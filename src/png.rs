@@ -0,0 +1,455 @@
+use std::io::{Read, Write};
+use crate::error::{Error, Result};
+use crate::tgaimage::Image;
+use crate::zlib::{compress, crc32, decompress};
+
+const SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+fn write_chunk(f: &mut dyn Write, kind: &[u8; 4], data: &[u8]) -> Result<()> {
+    f.write_all(&(data.len() as u32).to_be_bytes())?;
+    f.write_all(kind)?;
+    f.write_all(data)?;
+    f.write_all(&crc32(crc32(0, kind), data).to_be_bytes())?;
+    Ok(())
+}
+
+#[inline]
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+// Each row gets the filter whose output has the smallest sum of absolute values, the usual heuristic
+fn filter_rows(raw: &[u8], stride: usize, bpp: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(raw.len() + raw.len() / stride.max(1));
+    let zero = vec![0u8; stride];
+    let mut candidate = vec![0u8; stride];
+    let mut best = vec![0u8; stride];
+    for (y, row) in raw.chunks_exact(stride).enumerate() {
+        let up = if y == 0 { &zero[..] } else { &raw[(y - 1) * stride..y * stride] };
+        let mut best_filter = 0;
+        let mut best_cost = u64::MAX;
+        for filter in 0..5u8 {
+            for i in 0..stride {
+                let a = if i >= bpp { row[i - bpp] } else { 0 };
+                let c = if i >= bpp { up[i - bpp] } else { 0 };
+                let b = up[i];
+                candidate[i] = row[i].wrapping_sub(match filter {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => ((a as u16 + b as u16) / 2) as u8,
+                    _ => paeth(a, b, c),
+                });
+            }
+            let cost = candidate.iter().map(|&v| (v as i8).unsigned_abs() as u64).sum();
+            if cost < best_cost {
+                best_cost = cost;
+                best_filter = filter;
+                best.copy_from_slice(&candidate);
+            }
+        }
+        out.push(best_filter);
+        out.extend_from_slice(&best);
+    }
+    out
+}
+
+impl Image {
+    // 8 bits per channel: grayscale, RGB or RGBA depending on bytespp
+    pub fn write_png(&self, f: &mut dyn Write) -> Result<()> {
+        let color_type = match self.bytespp() {
+            1 => 0,
+            3 => 2,
+            _ => 6,
+        };
+        let mut ihdr = Vec::with_capacity(13);
+        ihdr.extend((self.width as u32).to_be_bytes());
+        ihdr.extend((self.height as u32).to_be_bytes());
+        ihdr.extend([8, color_type, 0, 0, 0]);
+
+        // Pixels are stored as BGR(A), PNG wants RGB(A)
+        let mut raw = self.data.clone();
+        if self.bytespp() >= 3 {
+            for px in raw.chunks_exact_mut(self.bytespp()) {
+                px.swap(0, 2);
+            }
+        }
+        let filtered = filter_rows(&raw, self.width * self.bytespp(), self.bytespp());
+
+        f.write_all(&SIGNATURE)?;
+        write_chunk(f, b"IHDR", &ihdr)?;
+        write_chunk(f, b"IDAT", &compress(&filtered))?;
+        write_chunk(f, b"IEND", &[])?;
+        Ok(())
+    }
+}
+
+// Start column, start row, column step and row step of the seven Adam7 passes
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2),
+];
+
+struct PngHeader {
+    width: usize,
+    height: usize,
+    depth: u8,
+    color_type: u8,
+    interlaced: bool,
+}
+
+impl PngHeader {
+    fn channels(&self) -> usize {
+        match self.color_type {
+            0 | 3 => 1,
+            2 => 3,
+            4 => 2,
+            _ => 4,
+        }
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.depth as usize
+    }
+}
+
+fn unfilter(data: &mut [u8], stride: usize, rows: usize, bpp: usize) -> Result<Vec<u8>> {
+    if data.len() < (stride + 1) * rows {
+        return Err(Error::InvalidFormat("Truncated PNG image data"));
+    }
+    let mut out = vec![0u8; stride * rows];
+    for y in 0..rows {
+        let filter = data[y * (stride + 1)];
+        let src = &data[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        let (done, rest) = out.split_at_mut(y * stride);
+        let up = if y == 0 { None } else { Some(&done[(y - 1) * stride..]) };
+        let row = &mut rest[..stride];
+        for i in 0..stride {
+            let a = if i >= bpp { row[i - bpp] } else { 0 };
+            let b = up.map_or(0, |u| u[i]);
+            let c = if i >= bpp { up.map_or(0, |u| u[i - bpp]) } else { 0 };
+            row[i] = src[i].wrapping_add(match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(Error::InvalidFormat("Invalid PNG filter type")),
+            });
+        }
+    }
+    Ok(out)
+}
+
+// Raw value of the n-th sample of a row, 16-bit samples are big-endian
+#[inline]
+fn sample(row: &[u8], n: usize, depth: u8) -> u16 {
+    match depth {
+        16 => u16::from_be_bytes([row[2 * n], row[2 * n + 1]]),
+        8 => row[n] as u16,
+        _ => {
+            let bit = n * depth as usize;
+            let shift = 8 - depth as usize - bit % 8;
+            ((row[bit / 8] >> shift) & ((1u8 << depth) - 1)) as u16
+        },
+    }
+}
+
+#[inline]
+fn to_u8(v: u16, depth: u8) -> u8 {
+    match depth {
+        16 => (v >> 8) as u8,
+        8 => v as u8,
+        _ => (v as u32 * 255 / ((1u32 << depth) - 1)) as u8,
+    }
+}
+
+impl Image {
+    // Any color type and bit depth, interlaced or not. Grayscale stays 1 byte per pixel, palettes
+    // expand to RGB and transparency (alpha channels or tRNS) gives RGBA; 16-bit samples are truncated.
+    pub fn read_png(f: &mut dyn Read) -> Result<Self> {
+        let mut data = Vec::new();
+        f.read_to_end(&mut data)?;
+        if data.len() < 8 || data[..8] != SIGNATURE {
+            return Err(Error::InvalidHeader);
+        }
+
+        let mut header = None;
+        let mut palette: Vec<[u8; 3]> = Vec::new();
+        let mut trns: Vec<u8> = Vec::new();
+        let mut idat = Vec::new();
+        let mut pos = 8;
+        loop {
+            let len_bytes = data.get(pos..pos + 8).ok_or(Error::InvalidFormat("Truncated PNG chunk"))?;
+            let len = u32::from_be_bytes([len_bytes[0], len_bytes[1], len_bytes[2], len_bytes[3]]) as usize;
+            let kind = &data[pos + 4..pos + 8];
+            let body = data.get(pos + 8..pos + 8 + len).ok_or(Error::InvalidFormat("Truncated PNG chunk"))?;
+            let crc = data.get(pos + 8 + len..pos + 12 + len).ok_or(Error::InvalidFormat("Truncated PNG chunk"))?;
+            if crc32(crc32(0, kind), body) != u32::from_be_bytes([crc[0], crc[1], crc[2], crc[3]]) {
+                return Err(Error::InvalidFormat("PNG chunk checksum mismatch"));
+            }
+            pos += 12 + len;
+            match kind {
+                b"IHDR" if body.len() == 13 => {
+                    let be = |i: usize| u32::from_be_bytes([body[i], body[i + 1], body[i + 2], body[i + 3]]) as usize;
+                    if body[10] != 0 || body[11] != 0 || body[12] > 1 {
+                        return Err(Error::InvalidHeader);
+                    }
+                    header = Some(PngHeader {width: be(0), height: be(4), depth: body[8], color_type: body[9], interlaced: body[12] == 1});
+                },
+                b"PLTE" => palette = body.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect(),
+                b"tRNS" => trns = body.to_vec(),
+                b"IDAT" => idat.extend_from_slice(body),
+                b"IEND" => break,
+                // Unknown critical chunks change how the image has to be decoded
+                _ if kind[0] & 0x20 == 0 => return Err(Error::InvalidFormat("Unsupported critical PNG chunk")),
+                _ => {},
+            }
+        }
+
+        let header = header.ok_or(Error::InvalidHeader)?;
+        let valid_depth = match header.color_type {
+            0 => [1, 2, 4, 8, 16].contains(&header.depth),
+            3 => [1, 2, 4, 8].contains(&header.depth),
+            2 | 4 | 6 => [8, 16].contains(&header.depth),
+            _ => false,
+        };
+        if !valid_depth || header.width == 0 || header.height == 0 || header.width > i32::MAX as usize || header.height > i32::MAX as usize {
+            return Err(Error::InvalidHeader);
+        }
+        if header.color_type == 3 && palette.is_empty() {
+            return Err(Error::InvalidFormat("PNG palette missing"));
+        }
+
+        let (w, h, depth) = (header.width, header.height, header.depth);
+        // Transparent color key of grayscale and RGB images
+        let key: Option<Vec<u16>> = match header.color_type {
+            0 | 2 if trns.len() >= 2 * header.channels() => {
+                Some(trns.chunks_exact(2).take(header.channels()).map(|c| u16::from_be_bytes([c[0], c[1]])).collect())
+            },
+            _ => None,
+        };
+        let bytespp = match header.color_type {
+            0 if key.is_none() => 1,
+            2 if key.is_none() => 3,
+            3 if trns.is_empty() => 3,
+            _ => 4,
+        };
+
+        let passes: &[(usize, usize, usize, usize)] = if header.interlaced { &ADAM7 } else { &[(0, 0, 1, 1)] };
+        // Each row of a pass is a filter byte and its packed samples, the data can't inflate to more
+        // and the header can't promise more pixels than it holds
+        let mut size = Some(0usize);
+        for &(x0, y0, dx, dy) in passes {
+            if x0 < w && y0 < h {
+                let (pw, ph) = ((w - x0).div_ceil(dx), (h - y0).div_ceil(dy));
+                let pass = pw.checked_mul(header.bits_per_pixel()).and_then(|bits| (bits.div_ceil(8) + 1).checked_mul(ph));
+                size = size.zip(pass).and_then(|(size, pass)| size.checked_add(pass));
+            }
+        }
+        let size = size.filter(|_| w.checked_mul(h).and_then(|n| n.checked_mul(bytespp)).is_some())
+            .ok_or(Error::InvalidFormat("PNG image too large"))?;
+        let mut raw = decompress(&idat, size)?;
+        if raw.len() < size {
+            return Err(Error::InvalidFormat("Truncated PNG image data"));
+        }
+        let mut image = Image::new(w, h, bytespp);
+        let filter_bpp = (header.bits_per_pixel() / 8).max(1);
+        let mut offset = 0;
+        for &(x0, y0, dx, dy) in passes {
+            if x0 >= w || y0 >= h {
+                continue;
+            }
+            let (pw, ph) = ((w - x0).div_ceil(dx), (h - y0).div_ceil(dy));
+            let stride = (pw * header.bits_per_pixel()).div_ceil(8);
+            let pixels = unfilter(raw.get_mut(offset..).unwrap_or(&mut []), stride, ph, filter_bpp)?;
+            offset += (stride + 1) * ph;
+
+            for (py, row) in pixels.chunks_exact(stride).enumerate() {
+                for px in 0..pw {
+                    let n = px * header.channels();
+                    let s = |c: usize| sample(row, n + c, depth);
+                    // RGBA
+                    let rgba = match header.color_type {
+                        0 => {
+                            let v = to_u8(s(0), depth);
+                            let opaque = key.as_ref().is_none_or(|k| k[0] != s(0));
+                            [v, v, v, if opaque { 255 } else { 0 }]
+                        },
+                        2 => {
+                            let opaque = key.as_ref().is_none_or(|k| k[..] != [s(0), s(1), s(2)]);
+                            [to_u8(s(0), depth), to_u8(s(1), depth), to_u8(s(2), depth), if opaque { 255 } else { 0 }]
+                        },
+                        3 => {
+                            let idx = s(0) as usize;
+                            let [r, g, b] = *palette.get(idx).ok_or(Error::InvalidFormat("PNG palette index out of range"))?;
+                            [r, g, b, trns.get(idx).copied().unwrap_or(255)]
+                        },
+                        4 => {
+                            let v = to_u8(s(0), depth);
+                            [v, v, v, to_u8(s(1), depth)]
+                        },
+                        _ => [to_u8(s(0), depth), to_u8(s(1), depth), to_u8(s(2), depth), to_u8(s(3), depth)],
+                    };
+                    let (x, y) = (x0 + px * dx, y0 + py * dy);
+                    let dst = &mut image.data[(y * w + x) * bytespp..][..bytespp];
+                    match bytespp {
+                        1 => dst[0] = rgba[0],
+                        3 => dst.copy_from_slice(&[rgba[2], rgba[1], rgba[0]]),
+                        _ => dst.copy_from_slice(&[rgba[2], rgba[1], rgba[0], rgba[3]]),
+                    }
+                }
+            }
+        }
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SIGNATURE;
+    use crate::tgaimage::Image;
+    use crate::zlib::{compress, crc32};
+
+    // Chunk types in file order, each checked against its CRC
+    fn chunks(data: &[u8]) -> Vec<String> {
+        assert_eq!(data[..8], SIGNATURE);
+        let mut kinds = Vec::new();
+        let mut pos = 8;
+        while pos < data.len() {
+            let len = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+            let crc = u32::from_be_bytes(data[pos + 8 + len..pos + 12 + len].try_into().unwrap());
+            assert_eq!(crc32(0, &data[pos + 4..pos + 8 + len]), crc);
+            kinds.push(String::from_utf8(data[pos + 4..pos + 8].to_vec()).unwrap());
+            pos += 12 + len;
+        }
+        kinds
+    }
+
+    // Smooth gradients and noise so that every filter type gets picked somewhere
+    fn test_image(w: usize, h: usize, bytespp: usize) -> Image {
+        let mut image = Image::new(w, h, bytespp);
+        for (i, v) in image.data.iter_mut().enumerate() {
+            let (x, y, c) = (i / bytespp % w, i / bytespp / w, i % bytespp);
+            *v = if y % 3 == 0 { (x * 7 + c * 50) as u8 } else { ((x * y * 31 + c * 97) % 251) as u8 };
+        }
+        image
+    }
+
+    #[test]
+    fn write_read_round_trip() {
+        for bytespp in [1, 3, 4] {
+            for (w, h) in [(1, 1), (17, 9), (64, 33)] {
+                let image = test_image(w, h, bytespp);
+                let mut out = Vec::new();
+                image.write_png(&mut out).unwrap();
+                assert_eq!(chunks(&out), ["IHDR", "IDAT", "IEND"]);
+                let copy = Image::read_png(&mut &out[..]).unwrap();
+                assert_eq!((copy.width, copy.height, copy.bytespp()), (w, h, bytespp));
+                assert!(copy.data == image.data, "{}x{}x{}", w, h, bytespp);
+            }
+        }
+    }
+
+    fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+        out.extend((data.len() as u32).to_be_bytes());
+        out.extend(kind);
+        out.extend(data);
+        out.extend(crc32(crc32(0, kind), data).to_be_bytes());
+    }
+
+    // A PNG with the given header fields, extra chunks before IDAT and unfiltered scanlines
+    fn png(w: u32, h: u32, depth: u8, color_type: u8, interlaced: bool, extra: &[(&[u8; 4], &[u8])], raw: &[u8]) -> Vec<u8> {
+        let mut out = SIGNATURE.to_vec();
+        let mut ihdr = w.to_be_bytes().to_vec();
+        ihdr.extend(h.to_be_bytes());
+        ihdr.extend([depth, color_type, 0, 0, interlaced as u8]);
+        chunk(&mut out, b"IHDR", &ihdr);
+        for (kind, data) in extra {
+            chunk(&mut out, kind, data);
+        }
+        chunk(&mut out, b"IDAT", &compress(raw));
+        chunk(&mut out, b"IEND", &[]);
+        out
+    }
+
+    fn decode(data: &[u8]) -> Image {
+        Image::read_png(&mut &data[..]).unwrap()
+    }
+
+    #[test]
+    fn filters() {
+        // Sub, up, paeth and average rows of an 8-bit grayscale image
+        let raw = [1, 10, 5, 5, 2, 1, 1, 1, 4, 0, 0, 0, 3, 0, 0, 0];
+        let image = decode(&png(3, 4, 8, 0, false, &[], &raw));
+        assert_eq!(image.bytespp(), 1);
+        assert_eq!(image.data, [10, 15, 20, 11, 16, 21, 11, 16, 21, 5, 10, 15]);
+    }
+
+    #[test]
+    fn palette_with_transparency() {
+        let plte = [255, 0, 0, 0, 255, 0, 0, 0, 255];
+        let image = decode(&png(2, 2, 8, 3, false, &[(b"PLTE", &plte), (b"tRNS", &[0, 128])], &[0, 0, 1, 0, 2, 1]));
+        assert_eq!(image.bytespp(), 4);
+        assert_eq!(image.data, [0, 0, 255, 0, 0, 255, 0, 128, 255, 0, 0, 255, 0, 255, 0, 128]);
+        // Without tRNS palettes expand to RGB
+        let image = decode(&png(2, 1, 8, 3, false, &[(b"PLTE", &plte)], &[0, 2, 1]));
+        assert_eq!(image.data, [255, 0, 0, 0, 255, 0]);
+    }
+
+    #[test]
+    fn sub_byte_depths() {
+        // 2-bit palette indices 0, 1, 2, 3, 2 packed into two bytes
+        let plte = [0, 0, 0, 10, 10, 10, 20, 20, 20, 30, 30, 30];
+        let image = decode(&png(5, 1, 2, 3, false, &[(b"PLTE", &plte)], &[0, 0b00_01_10_11, 0b10_000000]));
+        assert_eq!(image.data, [0, 0, 0, 10, 10, 10, 20, 20, 20, 30, 30, 30, 20, 20, 20]);
+        // 1 and 4-bit gray scale to the full range
+        assert_eq!(decode(&png(9, 1, 1, 0, false, &[], &[0, 0b1010_0000, 0b1000_0000])).data, [255, 0, 255, 0, 0, 0, 0, 0, 255]);
+        assert_eq!(decode(&png(3, 1, 4, 0, false, &[], &[0, 0x0f, 0x50])).data, [0, 255, 85]);
+    }
+
+    #[test]
+    fn wide_samples_and_gray_alpha() {
+        // 16-bit samples keep their high byte, gray+alpha becomes BGRA
+        let image = decode(&png(1, 1, 16, 2, false, &[], &[0, 0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc]));
+        assert_eq!(image.data, [0x9a, 0x56, 0x12]);
+        let image = decode(&png(2, 1, 8, 4, false, &[], &[0, 50, 255, 100, 0]));
+        assert_eq!(image.data, [50, 50, 50, 255, 100, 100, 100, 0]);
+        // A gray color key makes matching pixels transparent
+        let image = decode(&png(2, 1, 8, 0, false, &[(b"tRNS", &[0, 50])], &[0, 50, 60]));
+        assert_eq!(image.data, [50, 50, 50, 0, 60, 60, 60, 255]);
+    }
+
+    #[test]
+    fn interlaced() {
+        // Adam7 passes of a 3x3 image: (0, 0), (2, 0), row 2 at x 0 and 2, column 1 at y 0 and 2, row 1
+        let raw = [0, 10, 0, 12, 0, 16, 18, 0, 11, 0, 17, 0, 13, 14, 15];
+        let image = decode(&png(3, 3, 8, 0, true, &[], &raw));
+        assert_eq!(image.data, [10, 11, 12, 13, 14, 15, 16, 17, 18]);
+    }
+
+    #[test]
+    fn invalid_files() {
+        let read = |data: &[u8]| Image::read_png(&mut &data[..]);
+        // Too little data, more data than the header allows and dimensions nothing can hold
+        assert!(read(&png(3, 2, 8, 0, false, &[], &[0, 1, 2, 3, 0, 4])).is_err());
+        assert!(read(&png(1, 1, 8, 0, false, &[], &[0, 1, 0, 2])).is_err());
+        assert!(read(&png(1, 1, 8, 0, false, &[], &vec![0; 100_000])).is_err());
+        assert!(read(&png(0x7fffffff, 0x7fffffff, 16, 6, false, &[], &[0; 16])).is_err());
+        assert!(read(&png(100_000, 100_000, 8, 2, false, &[], &[0; 16])).is_err());
+        // Bad filter type, palette index past the palette and a damaged chunk
+        assert!(read(&png(1, 1, 8, 0, false, &[], &[5, 0])).is_err());
+        assert!(read(&png(1, 1, 8, 3, false, &[(b"PLTE", &[1, 2, 3])], &[0, 1])).is_err());
+        let mut data = png(1, 1, 8, 0, false, &[], &[0, 7]);
+        data[20] ^= 1;
+        assert!(read(&data).is_err());
+        assert!(read(&png(1, 1, 8, 0, false, &[], &[0, 7])[..40]).is_err());
+    }
+}
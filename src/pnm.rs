@@ -0,0 +1,219 @@
+use std::io::{Read, Write};
+use crate::error::{Error, Result};
+use crate::tgaimage::{convert_pixels, Image};
+
+// Netpbm readers may reject plain-format lines longer than this
+const MAX_LINE: usize = 70;
+
+fn write_plain(f: &mut dyn Write, values: &[u8]) -> Result<()> {
+    let mut line = String::with_capacity(MAX_LINE + 4);
+    for &v in values {
+        let s = v.to_string();
+        if !line.is_empty() && line.len() + 1 + s.len() > MAX_LINE {
+            writeln!(f, "{}", line)?;
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&s);
+    }
+    if !line.is_empty() {
+        writeln!(f, "{}", line)?;
+    }
+    Ok(())
+}
+
+// Whitespace separated header or plain-format token, '#' comments run to the end of the line
+fn next_token(data: &[u8], pos: &mut usize) -> Result<usize> {
+    loop {
+        match data.get(*pos) {
+            Some(b'#') => while data.get(*pos).is_some_and(|&c| c != b'\n') { *pos += 1; },
+            Some(c) if c.is_ascii_whitespace() => *pos += 1,
+            Some(_) => break,
+            None => return Err(Error::InvalidFormat("Truncated PNM file")),
+        }
+    }
+    let start = *pos;
+    while data.get(*pos).is_some_and(|c| c.is_ascii_digit()) {
+        *pos += 1;
+    }
+    std::str::from_utf8(&data[start..*pos]).ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or(Error::InvalidFormat("Invalid number in PNM file"))
+}
+
+impl Image {
+    // P2, P3, P5 and P6 with any maxval up to 65535, samples are rescaled to 0..255.
+    // Graymaps give 1 byte per pixel images, pixmaps 3 bytes per pixel.
+    pub fn read_pnm(f: &mut dyn Read) -> Result<Self> {
+        let mut data = Vec::new();
+        f.read_to_end(&mut data)?;
+        let (channels, ascii) = match data.get(..2) {
+            Some(b"P2") => (1, true),
+            Some(b"P3") => (3, true),
+            Some(b"P5") => (1, false),
+            Some(b"P6") => (3, false),
+            _ => return Err(Error::InvalidHeader),
+        };
+        let mut pos = 2;
+        let w = next_token(&data, &mut pos)?;
+        let h = next_token(&data, &mut pos)?;
+        let maxval = next_token(&data, &mut pos)?;
+        if w == 0 || h == 0 || maxval == 0 || maxval > 65535 {
+            return Err(Error::InvalidHeader);
+        }
+
+        let count = w.checked_mul(h).and_then(|n| n.checked_mul(channels)).ok_or(Error::InvalidHeader)?;
+        let samples: Vec<usize> = if ascii {
+            // Every sample takes at least a digit
+            if count > data.len() - pos {
+                return Err(Error::InvalidFormat("Truncated PNM file"));
+            }
+            (0..count).map(|_| next_token(&data, &mut pos)).collect::<Result<_>>()?
+        } else {
+            // A single whitespace character separates the header from the raster
+            let raster = data.get(pos + 1..).unwrap_or(&[]);
+            let size = if maxval > 255 { 2 } else { 1 };
+            if count.checked_mul(size).is_none_or(|n| raster.len() < n) {
+                return Err(Error::InvalidFormat("Truncated PNM file"));
+            }
+            match size {
+                1 => raster[..count].iter().map(|&v| v as usize).collect(),
+                _ => raster.chunks_exact(2).take(count).map(|c| u16::from_be_bytes([c[0], c[1]]) as usize).collect(),
+            }
+        };
+
+        let mut image = Image::new(w, h, channels);
+        for (px, src) in image.data.chunks_exact_mut(channels).zip(samples.chunks_exact(channels)) {
+            for (dst, &v) in px.iter_mut().rev().zip(src) {
+                *dst = (v.min(maxval) * 255 / maxval) as u8;
+            }
+        }
+        Ok(image)
+    }
+
+    // RGB samples in file order, grayscale is replicated and alpha dropped
+    fn rgb_samples(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.width * self.height * 3);
+        for px in self.data.chunks_exact(self.bytespp()) {
+            match *px {
+                [v] => out.extend([v, v, v]),
+                [b, g, r, ..] => out.extend([r, g, b]),
+                _ => unreachable!(),
+            }
+        }
+        out
+    }
+
+    // Color images are converted with the Rec. 601 luma weights
+    fn gray_samples(&self) -> Vec<u8> {
+        convert_pixels(&self.data, self.bytespp(), 1)
+    }
+
+    // P6, or P3 when ascii is set
+    pub fn write_ppm(&self, f: &mut dyn Write, ascii: bool) -> Result<()> {
+        writeln!(f, "{}\n{} {}\n255", if ascii { "P3" } else { "P6" }, self.width, self.height)?;
+        let samples = self.rgb_samples();
+        if ascii {
+            write_plain(f, &samples)
+        } else {
+            f.write_all(&samples)?;
+            Ok(())
+        }
+    }
+
+    // P5, or P2 when ascii is set
+    pub fn write_pgm(&self, f: &mut dyn Write, ascii: bool) -> Result<()> {
+        writeln!(f, "{}\n{} {}\n255", if ascii { "P2" } else { "P5" }, self.width, self.height)?;
+        let samples = self.gray_samples();
+        if ascii {
+            write_plain(f, &samples)
+        } else {
+            f.write_all(&samples)?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tgaimage::{convert_pixels, Image};
+
+    fn test_image(w: usize, h: usize, bytespp: usize) -> Image {
+        let mut image = Image::new(w, h, bytespp);
+        for (i, v) in image.data.iter_mut().enumerate() {
+            *v = (i * 37 % 256) as u8;
+        }
+        image
+    }
+
+    fn round_trip(image: &Image, gray: bool, ascii: bool) -> Image {
+        let mut out = Vec::new();
+        if gray { image.write_pgm(&mut out, ascii) } else { image.write_ppm(&mut out, ascii) }.unwrap();
+        Image::read_pnm(&mut &out[..]).unwrap()
+    }
+
+    #[test]
+    fn write_read_round_trip() {
+        for bytespp in [1, 3, 4] {
+            let image = test_image(13, 7, bytespp);
+            for ascii in [false, true] {
+                let ppm = round_trip(&image, false, ascii);
+                assert_eq!((ppm.width, ppm.height, ppm.bytespp()), (13, 7, 3));
+                assert!(ppm.data == convert_pixels(&image.data, bytespp, 3));
+                let pgm = round_trip(&image, true, ascii);
+                assert_eq!((pgm.width, pgm.height, pgm.bytespp()), (13, 7, 1));
+                assert!(pgm.data == convert_pixels(&image.data, bytespp, 1));
+            }
+        }
+    }
+
+    #[test]
+    fn plain_lines_stay_short() {
+        let mut out = Vec::new();
+        test_image(40, 3, 3).write_ppm(&mut out, true).unwrap();
+        assert!(String::from_utf8(out).unwrap().lines().all(|l| l.len() <= 70));
+    }
+
+    fn decode(data: &[u8]) -> Image {
+        Image::read_pnm(&mut &data[..]).unwrap()
+    }
+
+    #[test]
+    fn plain_and_raw() {
+        // The same 2x1 pixmap in both forms, with comments and odd whitespace in the header
+        let plain = decode(b"P3 # comment\n2\t1\n255\n10 20 30\n\n40 50 60\n");
+        let raw = decode(b"P6\n# comment\n2 1 255\n\x0a\x14\x1e\x28\x32\x3c");
+        assert_eq!(plain.data, [30, 20, 10, 60, 50, 40]);
+        assert_eq!(raw.data, plain.data);
+        let plain = decode(b"P2\n3 1\n255\n0 128 255");
+        let raw = decode(b"P5\n3 1\n255\n\x00\x80\xff");
+        assert_eq!(plain.bytespp(), 1);
+        assert_eq!(plain.data, [0, 128, 255]);
+        assert_eq!(raw.data, plain.data);
+    }
+
+    #[test]
+    fn maxval_scaling() {
+        // Small maxvals stretch to 0..255, 16-bit samples are big-endian
+        assert_eq!(decode(b"P2 3 1 15 0 5 15").data, [0, 85, 255]);
+        assert_eq!(decode(b"P5 2 1 65535\n\x00\x00\xff\xff").data, [0, 255]);
+        assert_eq!(decode(b"P2 2 1 4 2 9").data, [127, 255]);
+    }
+
+    #[test]
+    fn invalid_files() {
+        let read = |data: &[u8]| Image::read_pnm(&mut &data[..]);
+        // Missing samples, dimensions nothing can hold and bad header values
+        assert!(read(b"P6 2 2 255\n\x00\x00\x00").is_err());
+        assert!(read(b"P3 2 1 255 1 2 3 4 5").is_err());
+        assert!(read(b"P6 99999999999 99999999999 255\n\x00").is_err());
+        assert!(read(b"P2 99999999999 99999999999 255 0").is_err());
+        assert!(read(b"P5 0 1 255\n").is_err());
+        assert!(read(b"P5 1 1 70000\n\x00\x00").is_err());
+        assert!(read(b"P5 1 1 -3\n\x00").is_err());
+        assert!(read(b"P4 1 1\n\x00").is_err());
+    }
+}
+
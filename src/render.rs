@@ -1,49 +1,207 @@
-use std::old_io::IoResult;
-use tgaimage::{Image, Color};
-use model::Model;
-use geom::GeomActions;
-use vec::{Vec2, Vec3, Vec3f};
-use zbuffer::ZBuffer;
+use crate::error::Result;
+use crate::tgaimage::{Image, Color};
+use crate::model::Model;
+use crate::geom::{clip_face, clip_segment, is_culled, CullMode, GeomActions};
+use crate::shader::{FlatShader, GouraudShader, NormalShader, PhongShader, Shader};
+use crate::shadow::{ShadowMap, ShadowOptions};
+use crate::ssao::{apply_ssao, SsaoOptions};
+use crate::texture::{NormalMap, Texture};
+use crate::vec::{Mat4, Vec2, Vec3, Vec3f, Vec4f};
+use crate::zbuffer::{Depth, ZBuffer};
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Shading {
+    Flat,
+    Gouraud,
+    Phong,
+    Normals,
+    Wireframe,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Projection {
+    Orthographic,
+    Perspective,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    pub eye: Vec3f,
+    pub center: Vec3f,
+    pub up: Vec3f,
+    pub projection: Projection,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera {
+            eye: Vec3::new(0., 0., 3.),
+            center: Vec3::new(0., 0., 0.),
+            up: Vec3::new(0., 1., 0.),
+            projection: Projection::Perspective,
+        }
+    }
+}
+
+impl Camera {
+    pub fn transform(&self, width: usize, height: usize, depth: f32) -> Transform {
+        let projection = match self.projection {
+            Projection::Orthographic => Mat4::identity(),
+            Projection::Perspective => Mat4::projection((self.eye - self.center).norm()),
+        };
+        Transform::new(
+            Mat4::lookat(&self.eye, &self.center, &self.up),
+            projection,
+            Mat4::viewport(0., 0., width as f32, height as f32, depth),
+            )
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Transform {
+    pub modelview: Mat4,
+    pub projection: Mat4,
+    pub viewport: Mat4,
+    mvp: Mat4,
+}
+
+impl Transform {
+    pub fn new(modelview: Mat4, projection: Mat4, viewport: Mat4) -> Self {
+        Transform {modelview, projection, viewport, mvp: viewport * projection * modelview}
+    }
+
+    // Homogeneous screen coordinates of a world point, w is kept for perspective correction
+    #[inline]
+    pub fn apply(&self, v: &Vec3f) -> Vec4f {
+        self.mvp.transform_point(v)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RenderOptions {
+    pub shading: Shading,
+    pub cull: CullMode,
+    pub light_dir: Vec3f,
+    pub camera: Camera,
+    pub background: Color,
+    pub diffuse: Option<Texture>,
+    pub normal_map: Option<NormalMap>,
+    pub specular: Option<Texture>,
+    pub shadows: Option<ShadowOptions>,
+    pub ssao: Option<SsaoOptions>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            shading: Shading::Flat,
+            cull: CullMode::Back,
+            light_dir: Vec3::new(0., 0., -1.),
+            camera: Default::default(),
+            background: Color::rgba(0, 0, 0, 0),
+            diffuse: None,
+            normal_map: None,
+            specular: None,
+            shadows: None,
+            ssao: None,
+        }
+    }
+}
+
+fn draw_faces<S: Shader, D: Depth>(img: &mut Image, shader: &mut S, faces: &[usize], cull: CullMode, zbuf: &mut ZBuffer<D>) -> Result<()> {
+    for &faceid in faces {
+        img.draw_face(shader, faceid, cull, zbuf)?;
+    }
+    Ok(())
+}
+
+// Face ids bucketed by material, the first bucket holds the faces without one
+fn faces_by_material(model: &Model) -> Vec<Vec<usize>> {
+    let mut buckets = vec![Vec::new(); model.materials().len() + 1];
+    for faceid in 0..model.nfaces() {
+        let idx = model.face_info(faceid).material.map_or(0, |i| i + 1);
+        buckets[idx].push(faceid);
+    }
+    buckets
+}
+
+// Faces are culled on their visible outline, each edge is clipped on its own
+fn draw_wireframe(img: &mut Image, model: &Model, faces: &[usize], transform: &Transform, cull: CullMode) -> Result<()> {
+    let white = Color::rgba(255, 255, 255, 255);
+    let (width, height) = (img.width as f32, img.height as f32);
+    for &faceid in faces {
+        let pts: [Vec4f; 3] = std::array::from_fn(|i| transform.apply(model.face_vert(faceid, i)));
+        let outline: Vec<Vec3f> = clip_face(pts, width, height).iter().map(|p| p.project()).collect();
+        if outline.len() < 3 || is_culled(&outline, cull) {
+            continue;
+        }
+        for i in 0..3 {
+            // Edges reaching behind the camera would be projected mirrored
+            if let Some((from, to)) = clip_segment(pts[i], pts[(i + 1) % 3], width, height) {
+                let (from, to): (Vec2<f32>, Vec2<f32>) = (from.project().to_vec2(), to.project().to_vec2());
+                img.line(from, to, &white)?;
+            }
+        }
+    }
+    Ok(())
+}
 
 pub trait Renderer : GeomActions {
-    fn render(&mut self, model: Model) -> IoResult<()>;
+    fn render(&mut self, model: &Model, opts: &RenderOptions) -> Result<()>;
 }
 
 impl Renderer for Image {
-    fn render(&mut self, model: Model) -> IoResult<()> {
-        let light_dir: Vec3f = Vec3::new(0f32, 0f32, -1f32);
-        let mut zbuf = ZBuffer::new(self.width, self.height);
-        let depth = 256;
-        for faceid in range(0, model.nfaces()) {
-            let face = model.face(faceid);
-            let world_coords = [
-                model.vert(face[0]),
-                model.vert(face[1]),
-                model.vert(face[2]),
-                ];
-            let mut coords = [
-                world_coords[0] + &Vec3::new(1., 1., 1.),
-                world_coords[1] + &Vec3::new(1., 1., 1.),
-                world_coords[2] + &Vec3::new(1., 1., 1.),
-                ];
-            for i in range(0, 3) {
-                coords[i].scale(self.width as f32 - 1., self.height as f32 - 1., depth as f32 - 1.);
-                coords[i] = coords[i] + Vec3::new(0.5, 0.5, 0.5); // for the cast will round it properly
-            }
+    fn render(&mut self, model: &Model, opts: &RenderOptions) -> Result<()> {
+        let light_dir = opts.light_dir.normalize();
+        // Depth uses the same scale as x so that screen-space passes can compare them
+        let transform = opts.camera.transform(self.width, self.height, self.width as f32);
+        let mut zbuf: ZBuffer<f32> = ZBuffer::new(self.width, self.height);
+        self.fill(&opts.background);
 
-            let mut n: Vec3f = (*world_coords[2] - *world_coords[0]) ^ (*world_coords[1] - *world_coords[0]);
-            n = n.normalize();
-            let intensity = n.vec_mul(&light_dir);
-            if intensity > 0f32 {
-                let c = (intensity * 255. + 0.5) as u8;
-                try!(self.triangle(
-                        coords[0].to(),
-                        coords[1].to(),
-                        coords[2].to(),
-                        &Color::rgba(c, c, c, 0),
-                        &mut zbuf,
-                        ));
+        let shadow_map = match opts.shadows {
+            Some(shadow_opts) => Some(ShadowMap::render(
+                    model, &light_dir, &opts.camera.center, self.width, self.height, shadow_opts)?),
+            None => None,
+        };
+        let shadow = shadow_map.as_ref();
+        // Each material binds its own maps, the ones given in the options override them.
+        // They are only read for materials in use and shading modes that sample them.
+        let textured = matches!(opts.shading, Shading::Flat | Shading::Gouraud | Shading::Phong);
+        for (idx, faces) in faces_by_material(model).iter().enumerate() {
+            if faces.is_empty() {
+                continue;
             }
+            let textures = idx.checked_sub(1).filter(|_| textured).map(|i| model.materials()[i].load_textures());
+            let textures = textures.as_ref();
+            let diffuse = opts.diffuse.as_ref().or(textures.and_then(|t| t.diffuse.as_ref()));
+            let normal_map = opts.normal_map.as_ref().or(textures.and_then(|t| t.normal.as_ref()));
+            let specular = opts.specular.as_ref().or(textures.and_then(|t| t.specular.as_ref()));
+            match opts.shading {
+                Shading::Flat => {
+                    let mut shader = FlatShader {model, transform, light_dir, diffuse, shadow};
+                    draw_faces(self, &mut shader, faces, opts.cull, &mut zbuf)?;
+                },
+                Shading::Gouraud => {
+                    let mut shader = GouraudShader {model, transform, light_dir, diffuse, shadow};
+                    draw_faces(self, &mut shader, faces, opts.cull, &mut zbuf)?;
+                },
+                Shading::Phong => {
+                    let mut shader = PhongShader::new(model, transform, light_dir, opts.camera.eye);
+                    shader.diffuse = diffuse;
+                    shader.normal_map = normal_map;
+                    shader.specular = specular;
+                    shader.shadow = shadow;
+                    draw_faces(self, &mut shader, faces, opts.cull, &mut zbuf)?;
+                },
+                Shading::Normals => {
+                    let mut shader = NormalShader {model, transform};
+                    draw_faces(self, &mut shader, faces, opts.cull, &mut zbuf)?;
+                },
+                Shading::Wireframe => draw_wireframe(self, model, faces, &transform, opts.cull)?,
+            }
+        }
+        if let Some(ref ssao) = opts.ssao {
+            apply_ssao(self, &zbuf, ssao)?;
         }
         Ok(())
     }
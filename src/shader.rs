@@ -0,0 +1,252 @@
+use crate::geom::Interpolate;
+use crate::model::Model;
+use crate::render::Transform;
+use crate::shadow::ShadowMap;
+use crate::texture::{NormalMap, Texture};
+use crate::tgaimage::Color;
+use crate::vec::{Vec2, Vec2f, Vec3, Vec3f, Vec4f};
+
+pub struct VertexOut<V> {
+    pub pos: Vec4f,
+    pub varying: V,
+}
+
+pub trait Shader {
+    type Varying: Interpolate;
+
+    // Homogeneous screen-space position of the face corner plus whatever the fragment stage needs
+    fn vertex(&mut self, face: usize, corner: usize) -> VertexOut<Self::Varying>;
+
+    // None discards the fragment, leaving both color and depth untouched
+    fn fragment(&self, varying: &Self::Varying) -> Option<Color>;
+}
+
+pub fn face_normal(model: &Model, face: usize) -> Vec3f {
+    let v0 = *model.face_vert(face, 0);
+    let v1 = *model.face_vert(face, 1);
+    let v2 = *model.face_vert(face, 2);
+    ((v1 - v0) ^ (v2 - v0)).normalize()
+}
+
+// Shadowed points still get some light so they don't turn pitch black
+fn shadow_factor(shadow: Option<&ShadowMap>, pos: &Vec3f) -> f32 {
+    match shadow {
+        Some(map) => 0.3 + 0.7 * map.visibility(pos),
+        None => 1.,
+    }
+}
+
+// Vertex colors tint untextured surfaces, models without them are white
+fn vertex_color(model: &Model, face: usize, corner: usize) -> Vec3f {
+    model.color(face, corner).unwrap_or(Vec3::new(1., 1., 1.))
+}
+
+fn diffuse_color(diffuse: Option<&Texture>, uv: &Vec2f, tint: &Vec3f, intensity: f32) -> Color {
+    match diffuse {
+        Some(tex) => tex.sample(*uv).to_rgba().scale(intensity),
+        None => {
+            let c = |v: f32| (v * intensity * 255. + 0.5).clamp(0., 255.) as u8;
+            Color::rgba(c(tint.x), c(tint.y), c(tint.z), 255)
+        },
+    }
+}
+
+pub struct FlatShader<'a> {
+    pub model: &'a Model,
+    pub transform: Transform,
+    pub light_dir: Vec3f,
+    pub diffuse: Option<&'a Texture>,
+    pub shadow: Option<&'a ShadowMap>,
+}
+
+impl Shader for FlatShader<'_> {
+    type Varying = (f32, Vec2f, Vec3f, Vec3f);
+
+    fn vertex(&mut self, face: usize, corner: usize) -> VertexOut<(f32, Vec2f, Vec3f, Vec3f)> {
+        let intensity = -face_normal(self.model, face).vec_mul(&self.light_dir);
+        let uv = self.model.uv(face, corner).unwrap_or(Vec2::new(0., 0.));
+        let v = *self.model.face_vert(face, corner);
+        VertexOut {
+            pos: self.transform.apply(&v),
+            varying: (intensity, uv, v, vertex_color(self.model, face, corner)),
+        }
+    }
+
+    fn fragment(&self, &(intensity, uv, pos, tint): &(f32, Vec2f, Vec3f, Vec3f)) -> Option<Color> {
+        Some(diffuse_color(self.diffuse, &uv, &tint, intensity.max(0.) * shadow_factor(self.shadow, &pos)))
+    }
+}
+
+// Only fills the depth buffer, used for the shadow map pass
+pub struct DepthShader<'a> {
+    pub model: &'a Model,
+    pub transform: Transform,
+}
+
+impl Shader for DepthShader<'_> {
+    type Varying = ();
+
+    fn vertex(&mut self, face: usize, corner: usize) -> VertexOut<()> {
+        VertexOut {
+            pos: self.transform.apply(self.model.face_vert(face, corner)),
+            varying: (),
+        }
+    }
+
+    fn fragment(&self, _: &()) -> Option<Color> {
+        Some(Color::new())
+    }
+}
+
+pub struct NormalShader<'a> {
+    pub model: &'a Model,
+    pub transform: Transform,
+}
+
+impl Shader for NormalShader<'_> {
+    type Varying = Vec3f;
+
+    fn vertex(&mut self, face: usize, corner: usize) -> VertexOut<Vec3f> {
+        let n = self.model.normal(face, corner).unwrap_or_else(|| face_normal(self.model, face));
+        VertexOut {
+            pos: self.transform.apply(self.model.face_vert(face, corner)),
+            varying: n,
+        }
+    }
+
+    fn fragment(&self, n: &Vec3f) -> Option<Color> {
+        let n = n.normalize();
+        let c = |v: f32| ((v * 0.5 + 0.5) * 255. + 0.5).clamp(0., 255.) as u8;
+        Some(Color::rgba(c(n.x), c(n.y), c(n.z), 255))
+    }
+}
+
+// Per-vertex diffuse term interpolated across the face
+pub struct GouraudShader<'a> {
+    pub model: &'a Model,
+    pub transform: Transform,
+    pub light_dir: Vec3f,
+    pub diffuse: Option<&'a Texture>,
+    pub shadow: Option<&'a ShadowMap>,
+}
+
+impl Shader for GouraudShader<'_> {
+    type Varying = (f32, Vec2f, Vec3f, Vec3f);
+
+    fn vertex(&mut self, face: usize, corner: usize) -> VertexOut<(f32, Vec2f, Vec3f, Vec3f)> {
+        let n = self.model.normal(face, corner).unwrap_or_else(|| face_normal(self.model, face));
+        let intensity = (-n.normalize().vec_mul(&self.light_dir)).max(0.);
+        let uv = self.model.uv(face, corner).unwrap_or(Vec2::new(0., 0.));
+        let v = *self.model.face_vert(face, corner);
+        VertexOut {
+            pos: self.transform.apply(&v),
+            varying: (intensity, uv, v, vertex_color(self.model, face, corner)),
+        }
+    }
+
+    fn fragment(&self, &(intensity, uv, pos, tint): &(f32, Vec2f, Vec3f, Vec3f)) -> Option<Color> {
+        Some(diffuse_color(self.diffuse, &uv, &tint, intensity * shadow_factor(self.shadow, &pos)))
+    }
+}
+
+// Normals are interpolated (or taken from a normal map) and lighting is evaluated per pixel,
+// a specular map enables the specular term and gives its exponent
+pub struct PhongShader<'a> {
+    pub model: &'a Model,
+    pub transform: Transform,
+    pub light_dir: Vec3f,
+    pub eye: Vec3f,
+    pub diffuse: Option<&'a Texture>,
+    pub normal_map: Option<&'a NormalMap>,
+    pub specular: Option<&'a Texture>,
+    pub shadow: Option<&'a ShadowMap>,
+}
+
+impl<'a> PhongShader<'a> {
+    pub fn new(model: &'a Model, transform: Transform, light_dir: Vec3f, eye: Vec3f) -> Self {
+        PhongShader {
+            model,
+            transform,
+            light_dir,
+            eye,
+            diffuse: None,
+            normal_map: None,
+            specular: None,
+            shadow: None,
+        }
+    }
+
+    // Tangent and bitangent follow the u and v directions of the texture over the face,
+    // faces without usable texture coordinates get the x and y axes
+    fn tangent_basis(&self, face: usize) -> [Vec3f; 2] {
+        self.uv_basis(face).unwrap_or([Vec3::new(1., 0., 0.), Vec3::new(0., 1., 0.)])
+    }
+
+    fn uv_basis(&self, face: usize) -> Option<[Vec3f; 2]> {
+        let (uv0, uv1, uv2) = (self.model.uv(face, 0)?, self.model.uv(face, 1)?, self.model.uv(face, 2)?);
+        let p0 = *self.model.face_vert(face, 0);
+        let e1 = *self.model.face_vert(face, 1) - p0;
+        let e2 = *self.model.face_vert(face, 2) - p0;
+        let d1 = uv1 - uv0;
+        let d2 = uv2 - uv0;
+        let det = d1.x * d2.y - d2.x * d1.y;
+        if det.abs() < f32::EPSILON {
+            return None;
+        }
+        let r = 1. / det;
+        Some([(e1 * d2.y - e2 * d1.y) * r, (e2 * d1.x - e1 * d2.x) * r])
+    }
+
+    fn normal(&self, n: Vec3f, uv: Vec2f, [tangent, bitangent]: [Vec3f; 2]) -> Vec3f {
+        let n = n.normalize();
+        match self.normal_map {
+            None => n,
+            Some(map @ NormalMap::Object(_)) => map.sample(uv).normalize(),
+            Some(map @ NormalMap::Tangent(_)) => {
+                // Gram-Schmidt against the interpolated normal keeps the basis orthonormal
+                let mut t = tangent - n * n.vec_mul(&tangent);
+                if t.norm() < 1e-6 {
+                    // The tangent runs along the normal, any perpendicular direction will do
+                    let axis = if n.x.abs() < 0.9 { Vec3::new(1., 0., 0.) } else { Vec3::new(0., 1., 0.) };
+                    t = axis - n * n.vec_mul(&axis);
+                }
+                let t = t.normalize();
+                let handedness = if (n ^ t).vec_mul(&bitangent) < 0. { -1. } else { 1. };
+                let b = (n ^ t) * handedness;
+                let m = map.sample(uv);
+                (t * m.x + b * m.y + n * m.z).normalize()
+            },
+        }
+    }
+}
+
+impl Shader for PhongShader<'_> {
+    // Normal, uv, position, tint and the face's tangent basis
+    type Varying = (Vec3f, Vec2f, Vec3f, Vec3f, [Vec3f; 2]);
+
+    fn vertex(&mut self, face: usize, corner: usize) -> VertexOut<(Vec3f, Vec2f, Vec3f, Vec3f, [Vec3f; 2])> {
+        let n = self.model.normal(face, corner).unwrap_or_else(|| face_normal(self.model, face));
+        let uv = self.model.uv(face, corner).unwrap_or(Vec2::new(0., 0.));
+        let v = *self.model.face_vert(face, corner);
+        VertexOut {
+            pos: self.transform.apply(&v),
+            varying: (n, uv, v, vertex_color(self.model, face, corner), self.tangent_basis(face)),
+        }
+    }
+
+    fn fragment(&self, &(n, uv, pos, tint, basis): &(Vec3f, Vec2f, Vec3f, Vec3f, [Vec3f; 2])) -> Option<Color> {
+        let n = self.normal(n, uv, basis);
+        let l = self.light_dir * -1.;
+        let diff = n.vec_mul(&l).max(0.);
+        let spec = match self.specular {
+            Some(tex) if diff > 0. => {
+                let r = (n * (2. * n.vec_mul(&l)) - l).normalize();
+                let v = (self.eye - pos).normalize();
+                // Black texels still give a broad, dim highlight rather than a constant one
+                r.vec_mul(&v).max(0.).powf(5. + tex.sample(uv)[0] as f32)
+            },
+            _ => 0.,
+        };
+        Some(diffuse_color(self.diffuse, &uv, &tint, (diff + 0.6 * spec) * shadow_factor(self.shadow, &pos)))
+    }
+}
@@ -0,0 +1,78 @@
+use crate::error::Result;
+use crate::geom::{CullMode, GeomActions};
+use crate::model::Model;
+use crate::render::Transform;
+use crate::shader::DepthShader;
+use crate::tgaimage::Image;
+use crate::vec::{Mat4, Vec3, Vec3f};
+use crate::zbuffer::ZBuffer;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowOptions {
+    // Depth offset that keeps surfaces from shadowing themselves, the [-1, 1] scene depth maps to [0, 1]
+    pub bias: f32,
+    // Percentage-closer filtering over a (2 * radius + 1)^2 neighbourhood, 0 gives hard shadows
+    pub pcf_radius: usize,
+}
+
+impl Default for ShadowOptions {
+    fn default() -> Self {
+        ShadowOptions {bias: 0.01, pcf_radius: 1}
+    }
+}
+
+// Depth of the scene as seen from a directional light
+#[derive(Clone, Debug)]
+pub struct ShadowMap {
+    transform: Transform,
+    depth: ZBuffer<f32>,
+    opts: ShadowOptions,
+}
+
+impl ShadowMap {
+    pub fn render(model: &Model, light_dir: &Vec3f, center: &Vec3f, width: usize, height: usize, opts: ShadowOptions) -> Result<Self> {
+        let light_dir = light_dir.normalize();
+        let up = if light_dir.x.abs() < 0.9 { Vec3::new(1., 0., 0.) } else { Vec3::new(0., 1., 0.) };
+        let transform = Transform::new(
+            Mat4::lookat(&(*center - light_dir), center, &up),
+            Mat4::identity(),
+            Mat4::viewport(0., 0., width as f32, height as f32, 1.),
+            );
+
+        let mut target = Image::new(width, height, 1);
+        let mut depth = ZBuffer::new(width, height);
+        let mut shader = DepthShader {model, transform};
+        for faceid in 0..model.nfaces() {
+            target.draw_face(&mut shader, faceid, CullMode::None, &mut depth)?;
+        }
+        Ok(ShadowMap {transform, depth, opts})
+    }
+
+    #[inline]
+    pub fn depth(&self) -> &ZBuffer<f32> {
+        &self.depth
+    }
+
+    // Fraction of the light reaching a world-space point, from 0 (fully shadowed) to 1
+    pub fn visibility(&self, pos: &Vec3f) -> f32 {
+        let p = self.transform.apply(pos).project();
+        let (cx, cy) = (p.x.floor() as isize, p.y.floor() as isize);
+        let r = self.opts.pcf_radius as isize;
+        let mut lit = 0;
+        let mut total = 0;
+        for y in cy - r..=cy + r {
+            for x in cx - r..=cx + r {
+                total += 1;
+                if x < 0 || y < 0 || x >= self.depth.width as isize || y >= self.depth.height as isize {
+                    lit += 1;
+                    continue;
+                }
+                // The map keeps the surface closest to the light, i.e. the biggest z
+                if p.z + self.opts.bias >= *self.depth.val(x as usize, y as usize) {
+                    lit += 1;
+                }
+            }
+        }
+        lit as f32 / total as f32
+    }
+}
@@ -0,0 +1,67 @@
+use std::f32::consts::{FRAC_PI_2, PI};
+use crate::error::Result;
+use crate::tgaimage::Image;
+use crate::zbuffer::ZBuffer;
+
+#[derive(Clone, Copy, Debug)]
+pub struct SsaoOptions {
+    // How far, in pixels, each direction is marched looking for occluders
+    pub radius: f32,
+    // Number of directions evenly spread around each pixel
+    pub samples: usize,
+}
+
+impl Default for SsaoOptions {
+    fn default() -> Self {
+        SsaoOptions {radius: 16., samples: 8}
+    }
+}
+
+// Steepest slope of the depth buffer along a direction, as an elevation angle
+fn max_elevation(zbuf: &ZBuffer<f32>, x: usize, y: usize, dir: (f32, f32), radius: f32) -> f32 {
+    let z = *zbuf.val(x, y);
+    let mut max = 0f32;
+    let mut t = 1f32;
+    while t <= radius {
+        let sx = x as f32 + 0.5 + dir.0 * t;
+        let sy = y as f32 + 0.5 + dir.1 * t;
+        if sx < 0. || sy < 0. || sx >= zbuf.width as f32 || sy >= zbuf.height as f32 {
+            break;
+        }
+        let sz = *zbuf.val(sx as usize, sy as usize);
+        if sz.is_finite() {
+            max = max.max(((sz - z) / t).atan());
+        }
+        t += 1.;
+    }
+    max
+}
+
+// Share of the hemisphere above a pixel that is not blocked by nearby geometry, from 0 to 1.
+// The depth buffer has to be in the same units as x and y and grow towards the viewer.
+pub fn ambient_occlusion(zbuf: &ZBuffer<f32>, x: usize, y: usize, opts: &SsaoOptions) -> f32 {
+    if opts.samples == 0 {
+        return 1.;
+    }
+    let mut total = 0.;
+    for i in 0..opts.samples {
+        let angle = 2. * PI * i as f32 / opts.samples as f32;
+        total += FRAC_PI_2 - max_elevation(zbuf, x, y, (angle.cos(), angle.sin()), opts.radius);
+    }
+    total / (FRAC_PI_2 * opts.samples as f32)
+}
+
+// Darkens every rendered pixel (the ones with a finite depth) by its ambient occlusion
+pub fn apply_ssao(img: &mut Image, zbuf: &ZBuffer<f32>, opts: &SsaoOptions) -> Result<()> {
+    for y in 0..img.height.min(zbuf.height) {
+        for x in 0..img.width.min(zbuf.width) {
+            if !zbuf.val(x, y).is_finite() {
+                continue;
+            }
+            let ao = ambient_occlusion(zbuf, x, y, opts);
+            let c = img.get(x, y)?.scale(ao);
+            img.set(x, y, &c)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, Read};
+use std::path::Path;
+use crate::error::{Error, Location, Result};
+use crate::model::{Corner, Model};
+use crate::vec::{Vec3, Vec3f};
+
+// STL repeats the corners of every facet, equal positions are merged so the mesh stays connected
+#[derive(Default)]
+struct Mesh {
+    verts: Vec<Vec3f>,
+    norms: Vec<Vec3f>,
+    faces: Vec<Vec<Corner>>,
+    index: HashMap<[u32; 3], usize>,
+}
+
+impl Mesh {
+    fn vert(&mut self, v: Vec3f) -> usize {
+        let verts = &mut self.verts;
+        *self.index.entry([v.x.to_bits(), v.y.to_bits(), v.z.to_bits()]).or_insert_with(|| {
+            verts.push(v);
+            verts.len() - 1
+        })
+    }
+
+    // Exporters often leave the facet normal zeroed, the renderer then computes it from the winding
+    fn facet(&mut self, n: Vec3f, corners: &[Vec3f]) {
+        let norm = if n.norm() > 0. {
+            self.norms.push(n);
+            Some(self.norms.len() - 1)
+        } else {
+            None
+        };
+        let face = corners.iter().map(|&v| Corner {vert: self.vert(v), uv: None, norm}).collect();
+        self.faces.push(face);
+    }
+
+    fn into_model(self) -> Result<Model> {
+        Model::from_faces(self.verts, Vec::new(), self.norms, Vec::new(), self.faces)
+    }
+}
+
+// 80 bytes of header, the triangle count, then 50 bytes per triangle
+fn binary_size(data: &[u8]) -> Option<usize> {
+    let count = u32::from_le_bytes(data.get(80..84)?.try_into().ok()?);
+    Some(84 + 50 * count as usize)
+}
+
+fn read_binary(data: &[u8]) -> Result<Model> {
+    let size = binary_size(data).ok_or(Error::InvalidFormat("Truncated STL header"))?;
+    if data.len() < size {
+        return Err(Error::InvalidFormat("Truncated STL data"));
+    }
+    let mut mesh = Mesh::default();
+    for tri in data[84..size].chunks_exact(50) {
+        let f = |i: usize| f32::from_le_bytes([tri[4 * i], tri[4 * i + 1], tri[4 * i + 2], tri[4 * i + 3]]);
+        let v = |i: usize| Vec3::new(f(3 * i), f(3 * i + 1), f(3 * i + 2));
+        mesh.facet(v(0), &[v(1), v(2), v(3)]);
+    }
+    mesh.into_model()
+}
+
+fn read_ascii(text: &str, filename: Option<&Path>) -> Result<Model> {
+    let mut mesh = Mesh::default();
+    let mut normal = None;
+    let mut corners = Vec::new();
+
+    for (num, line) in text.lines().enumerate() {
+        let loc = Location {file: filename, line: num + 1};
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let vec3 = |first: usize| -> Result<Vec3f> {
+            Ok(Vec3::new(
+                loc.parse(parts.get(first), "an x coordinate")?,
+                loc.parse(parts.get(first + 1), "a y coordinate")?,
+                loc.parse(parts.get(first + 2), "a z coordinate")?,
+                ))
+        };
+        match parts.first() {
+            Some(&"facet") => {
+                if parts.get(1) != Some(&"normal") {
+                    return Err(loc.error(parts.get(1).unwrap_or(&""), "normal"));
+                }
+                normal = Some(vec3(2)?);
+                corners.clear();
+            },
+            Some(&"vertex") => {
+                if normal.is_none() {
+                    return Err(loc.error(parts[0], "a facet before its vertices"));
+                }
+                corners.push(vec3(1)?);
+            },
+            Some(&"endfacet") => {
+                let n = normal.take().ok_or_else(|| loc.error(parts[0], "a facet to close"))?;
+                if corners.len() < 3 {
+                    return Err(loc.error(parts[0], "at least three facet vertices"));
+                }
+                mesh.facet(n, &corners);
+            },
+            _ => {},
+        }
+    }
+    mesh.into_model()
+}
+
+// Binary files may also start with "solid", the size matching the triangle count tells them apart
+fn parse_stl(data: &[u8], filename: Option<&Path>) -> Result<Model> {
+    let binary = binary_size(data) == Some(data.len()) || !data.starts_with(b"solid");
+    if binary {
+        return read_binary(data);
+    }
+    let text = std::str::from_utf8(data).map_err(|_| Error::InvalidFormat("STL text is not valid UTF-8"))?;
+    read_ascii(text, filename)
+}
+
+pub fn read_stl(input: &mut dyn BufRead) -> Result<Model> {
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+    parse_stl(&data, None)
+}
+
+pub fn read_stl_file<P: AsRef<Path>>(filename: P) -> Result<Model> {
+    let filename = filename.as_ref();
+    let mut data = Vec::new();
+    File::open(filename)?.read_to_end(&mut data)?;
+    parse_stl(&data, Some(filename))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::read_stl;
+    use crate::model::Model;
+    use crate::vec::Vec3;
+
+    const ASCII: &str = "solid square
+facet normal 0 0 1
+  outer loop
+    vertex 0 0 0
+    vertex 1 0 0
+    vertex 1 1 0
+  endloop
+endfacet
+facet normal 0 0 0
+  outer loop
+    vertex 0 0 0
+    vertex 1 1 0
+    vertex 0 1 0
+  endloop
+endfacet
+endsolid square
+";
+
+    const FACETS: [[f32; 12]; 2] = [
+        [0., 0., 1., 0., 0., 0., 1., 0., 0., 1., 1., 0.],
+        [0., 0., 0., 0., 0., 0., 1., 1., 0., 0., 1., 0.],
+    ];
+
+    fn binary(header: &[u8], facets: &[[f32; 12]]) -> Vec<u8> {
+        let mut data = header.to_vec();
+        data.resize(80, b' ');
+        data.extend((facets.len() as u32).to_le_bytes());
+        for facet in facets {
+            data.extend(facet.iter().flat_map(|v| v.to_le_bytes()));
+            data.extend([0, 0]);
+        }
+        data
+    }
+
+    // Both facets share two corners, a zero normal is left for the renderer to compute
+    fn check_square(model: &Model) {
+        assert_eq!(model.nfaces(), 2);
+        assert_eq!(model.nverts(), 4);
+        assert_eq!(model.nnorms(), 1);
+        assert_eq!(model.face(0)[0].vert, model.face(1)[0].vert);
+        assert_eq!(model.face(0)[2].vert, model.face(1)[1].vert);
+        assert_eq!(model.face_vert(1, 2), &Vec3::new(0., 1., 0.));
+        assert_eq!(model.normal(0, 0), Some(Vec3::new(0., 0., 1.)));
+        assert_eq!(model.normal(1, 0), None);
+    }
+
+    #[test]
+    fn ascii() {
+        check_square(&ASCII.parse().unwrap());
+    }
+
+    #[test]
+    fn binary_file() {
+        let data = binary(b"exported", &FACETS);
+        check_square(&read_stl(&mut &data[..]).unwrap());
+        check_square(&Model::from_reader(&mut &data[..]).unwrap());
+    }
+
+    #[test]
+    fn binary_with_solid_header() {
+        let data = binary(b"solid square", &FACETS);
+        check_square(&read_stl(&mut &data[..]).unwrap());
+        check_square(&Model::from_reader(&mut &data[..]).unwrap());
+    }
+
+    #[test]
+    fn truncated_binary() {
+        let data = binary(b"exported", &FACETS);
+        assert!(read_stl(&mut &data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn invalid_ascii() {
+        assert!("solid x\nvertex 0 0 0\n".parse::<Model>().is_err());
+        assert!("solid x\nfacet normal 0 0 1\nvertex 0 0 0\nvertex 1 0 0\nendfacet\n".parse::<Model>().is_err());
+    }
+}
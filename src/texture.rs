@@ -0,0 +1,117 @@
+use std::fmt;
+use std::path::Path;
+use crate::error::Result;
+use crate::tgaimage::{Color, Image};
+use crate::vec::{Vec2f, Vec3, Vec3f};
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Wrap {
+    Repeat,
+    Clamp,
+}
+
+#[derive(Clone)]
+pub struct Texture {
+    image: Image,
+    pub filter: Filter,
+    pub wrap: Wrap,
+}
+
+impl fmt::Debug for Texture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Texture {{ {}x{}x{}, filter: {:?}, wrap: {:?} }}",
+               self.image.width, self.image.height, self.image.bytespp(), self.filter, self.wrap)
+    }
+}
+
+impl Texture {
+    pub fn new(image: Image) -> Self {
+        Texture {image, filter: Filter::Bilinear, wrap: Wrap::Repeat}
+    }
+
+    pub fn read_tga_file<P: AsRef<Path>>(filename: P) -> Result<Self> {
+        Ok(Texture::new(Image::read_tga_file(filename)?))
+    }
+
+    // TGA, PNG, PPM/PGM or BMP, picked by the file contents
+    pub fn read_file<P: AsRef<Path>>(filename: P) -> Result<Self> {
+        Ok(Texture::new(Image::read_file(filename)?))
+    }
+
+    #[inline]
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    #[inline]
+    fn address(&self, i: isize, size: usize) -> usize {
+        match self.wrap {
+            Wrap::Repeat => i.rem_euclid(size as isize) as usize,
+            Wrap::Clamp => i.clamp(0, size as isize - 1) as usize,
+        }
+    }
+
+    // Images are stored top row first while v grows upwards
+    #[inline]
+    fn texel(&self, x: isize, y: isize) -> Color {
+        let x = self.address(x, self.image.width);
+        let y = self.address(y, self.image.height);
+        self.image.get(x, self.image.height - 1 - y).unwrap()
+    }
+
+    pub fn sample(&self, uv: Vec2f) -> Color {
+        let u = uv.x * self.image.width as f32;
+        let v = uv.y * self.image.height as f32;
+        match self.filter {
+            Filter::Nearest => self.texel(u.floor() as isize, v.floor() as isize),
+            Filter::Bilinear => {
+                let u = u - 0.5;
+                let v = v - 0.5;
+                let x0 = u.floor();
+                let y0 = v.floor();
+                let fx = u - x0;
+                let fy = v - y0;
+                let (x0, y0) = (x0 as isize, y0 as isize);
+                let c00 = self.texel(x0, y0);
+                let c10 = self.texel(x0 + 1, y0);
+                let c01 = self.texel(x0, y0 + 1);
+                let c11 = self.texel(x0 + 1, y0 + 1);
+
+                let mut result = c00.clone();
+                for i in 0..self.image.bytespp() {
+                    let top = c00[i] as f32 * (1. - fx) + c10[i] as f32 * fx;
+                    let bottom = c01[i] as f32 * (1. - fx) + c11[i] as f32 * fx;
+                    result[i] = (top * (1. - fy) + bottom * fy + 0.5) as u8;
+                }
+                result
+            },
+        }
+    }
+}
+
+// Normals are stored as RGB with each component mapped from [-1, 1] to [0, 255]
+#[derive(Clone, Debug)]
+pub enum NormalMap {
+    Tangent(Texture),
+    Object(Texture),
+}
+
+impl NormalMap {
+    pub fn texture(&self) -> &Texture {
+        match *self {
+            NormalMap::Tangent(ref tex) | NormalMap::Object(ref tex) => tex,
+        }
+    }
+
+    pub fn sample(&self, uv: Vec2f) -> Vec3f {
+        let c = self.texture().sample(uv).to_rgba();
+        let f = |v: u8| v as f32 / 255. * 2. - 1.;
+        Vec3::new(f(c[2]), f(c[1]), f(c[0]))
+    }
+}
@@ -1,11 +1,11 @@
-use std::old_io::{File, Reader, IoResult, IoError, IoErrorKind};
-use std::old_path::posix::Path;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
 use std::ops::{Index, IndexMut};
-use std::default::Default;
-use std::ptr::{set_memory, copy_memory, copy_nonoverlapping_memory};
-use std::mem::transmute;
+use std::path::Path;
+use crate::error::{Error, Result};
+use crate::tgameta::{self, TgaMetadata};
 
-#[packed]
 #[derive(PartialEq, Clone, Default)]
 struct Header {
     id_length: u8,
@@ -23,48 +23,41 @@ struct Header {
 }
 
 impl Header {
-    pub fn from_stream(buf: &mut Reader) -> IoResult<Self> {
-        let idl = try!(buf.read_u8());
-        let cmt = try!(buf.read_u8());
-        let dtc = try!(buf.read_u8());
-        let cmo = try!(buf.read_le_i16());
-        let cml = try!(buf.read_le_i16());
-        let cmd = try!(buf.read_u8());
-        let xo = try!(buf.read_le_i16());
-        let yo = try!(buf.read_le_i16());
-        let w = try!(buf.read_le_i16());
-        let h = try!(buf.read_le_i16());
-        let bpp = try!(buf.read_u8());
-        let id = try!(buf.read_u8());
+    pub fn from_stream(buf: &mut dyn Read) -> Result<Self> {
+        let mut raw = [0u8; 18];
+        buf.read_exact(&mut raw)?;
+        let le_i16 = |off: usize| i16::from_le_bytes([raw[off], raw[off + 1]]);
         Ok(Header {
-            id_length: idl,
-            color_map_type: cmt,
-            data_type_code: dtc,
-            color_map_origin: cmo,
-            color_map_length: cml,
-            color_map_depth: cmd,
-            x_origin: xo,
-            y_origin: yo,
-            width: w,
-            height: h,
-            bits_per_pixel: bpp,
-            image_descriptor: id
+            id_length: raw[0],
+            color_map_type: raw[1],
+            data_type_code: raw[2],
+            color_map_origin: le_i16(3),
+            color_map_length: le_i16(5),
+            color_map_depth: raw[7],
+            x_origin: le_i16(8),
+            y_origin: le_i16(10),
+            width: le_i16(12),
+            height: le_i16(14),
+            bits_per_pixel: raw[16],
+            image_descriptor: raw[17]
         })
     }
 
-    pub fn to_stream(&self, buf: &mut Writer) -> IoResult<()> {
-        try!(buf.write_u8(self.id_length));
-        try!(buf.write_u8(self.color_map_type));
-        try!(buf.write_u8(self.data_type_code));
-        try!(buf.write_le_i16(self.color_map_origin));
-        try!(buf.write_le_i16(self.color_map_length));
-        try!(buf.write_u8(self.color_map_depth));
-        try!(buf.write_le_i16(self.x_origin));
-        try!(buf.write_le_i16(self.y_origin));
-        try!(buf.write_le_i16(self.width));
-        try!(buf.write_le_i16(self.height));
-        try!(buf.write_u8(self.bits_per_pixel));
-        try!(buf.write_u8(self.image_descriptor));
+    // Number of alpha bits per pixel, the low nibble of the image descriptor
+    pub fn alpha_bits(&self) -> u8 {
+        self.image_descriptor & 0x0f
+    }
+
+    pub fn to_stream(&self, buf: &mut dyn Write) -> Result<()> {
+        buf.write_all(&[self.id_length, self.color_map_type, self.data_type_code])?;
+        buf.write_all(&self.color_map_origin.to_le_bytes())?;
+        buf.write_all(&self.color_map_length.to_le_bytes())?;
+        buf.write_all(&[self.color_map_depth])?;
+        buf.write_all(&self.x_origin.to_le_bytes())?;
+        buf.write_all(&self.y_origin.to_le_bytes())?;
+        buf.write_all(&self.width.to_le_bytes())?;
+        buf.write_all(&self.height.to_le_bytes())?;
+        buf.write_all(&[self.bits_per_pixel, self.image_descriptor])?;
         Ok(())
     }
 }
@@ -84,7 +77,7 @@ impl Color {
     }
 
     pub fn rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
-        Color {b: b, g: g, r: r, a: a, bytespp: 4}
+        Color {b, g, r, a, bytespp: 4}
     }
 
     pub fn from_raw(raw: &[u8], bpp: usize) -> Self {
@@ -92,48 +85,167 @@ impl Color {
         let g = if bpp > 1 { raw[1] } else { 0 };
         let r = if bpp > 2 { raw[2] } else { 0 };
         let a = if bpp > 3 { raw[3] } else { 0 };
-        Color {b: b, g: g, r: r, a: a, bytespp: bpp}
+        Color {b, g, r, a, bytespp: bpp}
     }
 
-    pub fn from_stream(buf: &mut Reader, bpp: usize) -> IoResult<Self> {
-        let b = try!(buf.read_u8());
-        let g = if bpp > 1 { try!(buf.read_u8()) } else { 0 };
-        let r = if bpp > 2 { try!(buf.read_u8()) } else { 0 };
-        let a = if bpp > 3 { try!(buf.read_u8()) } else { 0 };
-        Ok(Color {b: b, g: g, r: r, a: a, bytespp: bpp})
+    pub fn from_stream(buf: &mut dyn Read, bpp: usize) -> Result<Self> {
+        let mut raw = [0u8; 4];
+        buf.read_exact(&mut raw[..bpp])?;
+        Ok(Color::from_raw(&raw, bpp))
+    }
+
+    #[inline]
+    pub fn bytespp(&self) -> usize {
+        self.bytespp
+    }
+
+    // Grayscale colors are expanded so they can be written into color images
+    pub fn to_rgba(&self) -> Self {
+        if self.bytespp == 1 {
+            Color {b: self.b, g: self.b, r: self.b, a: 255, bytespp: 4}
+        } else if self.bytespp == 3 {
+            Color {a: 255, bytespp: 4, ..*self}
+        } else {
+            self.clone()
+        }
+    }
+
+    pub fn scale(&self, k: f32) -> Self {
+        let f = |v: u8| (v as f32 * k).clamp(0., 255.) as u8;
+        Color {b: f(self.b), g: f(self.g), r: f(self.r), ..*self}
+    }
+}
+
+impl Default for Color {
+    fn default() -> Self {
+        Color::new()
     }
 }
 
 impl Index<usize> for Color {
     type Output = u8;
 
-    fn index<'a>(&'a self, _index: &usize) -> &'a u8 {
-        assert!(*_index < 4);
-
-        match *_index {
-            0 => &(self.b),
-            1 => &(self.g),
-            2 => &(self.r),
-            3 => &(self.a),
-            _ => panic!("Oops!")
+    fn index(&self, index: usize) -> &u8 {
+        match index {
+            0 => &self.b,
+            1 => &self.g,
+            2 => &self.r,
+            3 => &self.a,
+            _ => panic!("Color component index out of range: {}", index)
         }
     }
 }
 
 impl IndexMut<usize> for Color {
-    type Output = u8;
+    fn index_mut(&mut self, index: usize) -> &mut u8 {
+        match index {
+            0 => &mut self.b,
+            1 => &mut self.g,
+            2 => &mut self.r,
+            3 => &mut self.a,
+            _ => panic!("Color component index out of range: {}", index)
+        }
+    }
+}
 
-    fn index_mut<'a>(&'a mut self, index: &usize) -> &'a mut u8 {
-        assert!(*index < 4);
+// Palette of a color-mapped image, expanded to BGR or BGRA entries
+struct ColorMap {
+    origin: usize,
+    entries: Vec<u8>,
+    bytespp: usize,
+}
 
-        match *index {
-            0 => &mut (self.b),
-            1 => &mut (self.g),
-            2 => &mut (self.r),
-            3 => &mut (self.a),
-            _ => panic!("Oops!")
+impl ColorMap {
+    // Alpha in 16 and 32-bit entries only counts when the descriptor declares attribute bits
+    fn from_stream(buf: &mut dyn Read, header: &Header) -> Result<Self> {
+        let length = header.color_map_length as u16 as usize;
+        let alpha = header.alpha_bits() > 0;
+        let (size, bytespp) = match header.color_map_depth {
+            15 => (2, 3),
+            16 => (2, if alpha { 4 } else { 3 }),
+            24 => (3, 3),
+            32 => (4, 4),
+            _ => return Err(Error::InvalidHeader),
+        };
+        let mut raw = vec![0u8; length * size];
+        buf.read_exact(&mut raw)?;
+        let mut entries = match size {
+            2 => expand_high_color(&raw, bytespp == 4),
+            _ => raw,
+        };
+        if size == 4 && !alpha {
+            entries.chunks_exact_mut(4).for_each(|c| c[3] = 255);
+        }
+        Ok(ColorMap {origin: header.color_map_origin as u16 as usize, entries, bytespp})
+    }
+
+    fn expand(&self, indices: &[u8], index_size: usize, w: usize, h: usize) -> Result<Image> {
+        let mut image = Image::new(w, h, self.bytespp);
+        for (px, raw) in image.data.chunks_exact_mut(self.bytespp).zip(indices.chunks_exact(index_size)) {
+            let index = if index_size == 2 { u16::from_le_bytes([raw[0], raw[1]]) as usize } else { raw[0] as usize };
+            let entry = index.checked_sub(self.origin)
+                .and_then(|i| self.entries.get(i * self.bytespp..(i + 1) * self.bytespp))
+                .ok_or(Error::InvalidFormat("Color map index out of range"))?;
+            px.copy_from_slice(entry);
+        }
+        Ok(image)
+    }
+}
+
+// A1R5G5B5 pixels to BGR, or BGRA when the attribute bit is kept as alpha
+fn expand_high_color(raw: &[u8], alpha: bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(raw.len() / 2 * if alpha { 4 } else { 3 });
+    for c in raw.chunks_exact(2) {
+        let v = u16::from_le_bytes([c[0], c[1]]);
+        // Five bits per channel, replicated into the low bits
+        let expand = |shift: u16| { let c = ((v >> shift) & 0x1f) as u8; (c << 3) | (c >> 2) };
+        out.extend([expand(0), expand(5), expand(10)]);
+        if alpha {
+            out.push(if v & 0x8000 != 0 { 255 } else { 0 });
+        }
+    }
+    out
+}
+
+// Pixels with another number of bytes each: gray is replicated, color is reduced with the
+// Rec. 601 luma weights and missing alpha is opaque
+pub(crate) fn convert_pixels(data: &[u8], from: usize, to: usize) -> Vec<u8> {
+    if from == to {
+        return data.to_vec();
+    }
+    let mut out = Vec::with_capacity(data.len() / from * to);
+    for px in data.chunks_exact(from) {
+        let (b, g, r, a) = match *px {
+            [v] => (v, v, v, 255),
+            [b, g, r] => (b, g, r, 255),
+            [b, g, r, a, ..] => (b, g, r, a),
+            _ => unreachable!(),
+        };
+        match to {
+            1 => out.push((0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32 + 0.5) as u8),
+            3 => out.extend([b, g, r]),
+            _ => out.extend([b, g, r, a]),
         }
     }
+    out
+}
+
+// Index of the color map entry nearest to px
+fn closest_entry(entries: &[u8], bytespp: usize, px: &[u8]) -> u8 {
+    let distance = |e: &[u8]| e.iter().zip(px).map(|(&a, &b)| (a as i32 - b as i32).pow(2)).sum::<i32>();
+    entries.chunks_exact(bytespp).enumerate().min_by_key(|&(_, e)| distance(e)).map_or(0, |(i, _)| i as u8)
+}
+
+// The attribute bit is set for opaque pixels, alpha below 128 clears it
+fn pack_high_color(px: &[u8]) -> [u8; 2] {
+    let (b, g, r, a) = match *px {
+        [v] => (v, v, v, 255),
+        [b, g, r] => (b, g, r, 255),
+        [b, g, r, a, ..] => (b, g, r, a),
+        _ => unreachable!(),
+    };
+    let v = ((r as u16 >> 3) << 10) | ((g as u16 >> 3) << 5) | (b as u16 >> 3) | if a >= 128 { 0x8000 } else { 0 };
+    v.to_le_bytes()
 }
 
 const GRAYSCALE: u8 = 1;
@@ -142,92 +254,133 @@ const RGBA: u8 = 4;
 
 #[derive(PartialEq, Clone)]
 pub struct Image {
-    data: Vec<u8>,
+    pub(crate) data: Vec<u8>,
     pub width: usize,
     pub height: usize,
-    bytespp: usize
+    bytespp: usize,
+    pub metadata: TgaMetadata
 }
 
 impl Image {
-    pub fn read_tga_file(filename: &str) -> IoResult<Self> {
-        let p = Path::new(filename);
-        let mut f = try!(File::open(&p));
-        let header = try!(Header::from_stream(&mut f));
+    pub fn read_tga_file<P: AsRef<Path>>(filename: P) -> Result<Self> {
+        let mut f = BufReader::new(File::open(filename)?);
+        Image::read_tga(&mut f)
+    }
+
+    pub fn read_tga(f: &mut dyn Read) -> Result<Self> {
+        // The footer of TGA 2.0 files points back to the extension and developer areas
+        let mut file = Vec::new();
+        f.read_to_end(&mut file)?;
+        let f = &mut file.as_slice();
+        let header = Header::from_stream(f)?;
 
         let w = header.width;
         let h = header.height;
-        let bpp = header.bits_per_pixel >> 3;
-        if w <= 0 || h <= 0 || (bpp != GRAYSCALE && bpp != RGB && bpp != RGBA) {
-            return Err(IoError{kind: IoErrorKind::MismatchedFileTypeForOperation, desc: "Invalid header format", detail: None})
+        let mapped = matches!(header.data_type_code, 1 | 9);
+        // Color-mapped pixels are 8 or 16-bit indices into the color map, 15 and 16-bit
+        // true-color pixels take two bytes
+        let bpp = header.bits_per_pixel.div_ceil(8);
+        let high_color = !mapped && (header.bits_per_pixel == 15 || header.bits_per_pixel == 16);
+        let valid = if mapped {
+            header.color_map_type == 1 && (header.bits_per_pixel == 8 || header.bits_per_pixel == 16)
+        } else if high_color {
+            matches!(header.data_type_code, 2 | 10)
+        } else {
+            header.bits_per_pixel % 8 == 0 && (bpp == GRAYSCALE || bpp == RGB || bpp == RGBA)
+        };
+        if w <= 0 || h <= 0 || !valid {
+            return Err(Error::InvalidHeader)
         }
 
         let w = w as usize;
         let h = h as usize;
 
+        // The image ID and the color map come between the header and the pixels
+        let mut id = vec![0u8; header.id_length as usize];
+        f.read_exact(&mut id)?;
+        let color_map = if header.color_map_type == 1 {
+            Some(ColorMap::from_stream(f, &header)?)
+        } else {
+            None
+        };
+
         let nbytes: usize = (bpp as usize) * w * h;
         let data = match header.data_type_code {
-            2 | 3 => {
-                try!(f.read_exact(nbytes))
+            1..=3 => {
+                let mut data = vec![0u8; nbytes];
+                f.read_exact(&mut data)?;
+                data
             },
-            10 | 11 => {
-                try!(Image::load_rle_data(w, h, bpp as usize, &mut f))
+            9..=11 => {
+                Image::load_rle_data(w, h, bpp as usize, f)?
             },
             _ => {
-                return Err(IoError{kind: IoErrorKind::MismatchedFileTypeForOperation, desc: "Invalid header format", detail: None})
+                return Err(Error::InvalidHeader)
             }
         };
 
-        let mut result = Image {
-            data: data,
-            width: w,
-            height: h,
-            bytespp: bpp as usize
-        };
+        // Pixels in file order to an image, shared with the postage stamp
+        let alpha = header.alpha_bits() > 0;
+        let decode = |data: Vec<u8>, w: usize, h: usize| -> Result<Image> {
+            let mut result = match color_map {
+                Some(ref map) if mapped => map.expand(&data, bpp as usize, w, h)?,
+                _ if high_color => {
+                    let alpha = alpha && header.bits_per_pixel == 16;
+                    Image::from_data(expand_high_color(&data, alpha), w, h, if alpha { 4 } else { 3 })
+                },
+                _ => Image::from_data(data, w, h, bpp as usize),
+            };
+            // Without attribute bits the fourth byte of 32-bit pixels carries no alpha
+            if !mapped && bpp == RGBA && !alpha {
+                result.data.chunks_exact_mut(4).for_each(|px| px[3] = 255);
+            }
 
-        if (header.image_descriptor & 0x20) == 0 {
-            try!(result.flip_vertically());
-        }
-        if (header.image_descriptor & 0x10) != 0 {
-            try!(result.flip_horizontally());
-        }
+            if (header.image_descriptor & 0x20) == 0 {
+                result.flip_vertically()?;
+            }
+            if (header.image_descriptor & 0x10) != 0 {
+                result.flip_horizontally()?;
+            }
+            Ok(result)
+        };
 
+        let mut result = decode(data, w, h)?;
+        let (extension, developer) = tgameta::read_areas(&file, bpp as usize, &decode);
+        result.metadata = TgaMetadata {id, extension, developer};
         Ok(result)
     }
 
-    fn load_rle_data<'a>(w: usize, h: usize, bpp: usize, buf: &'a mut Reader) -> IoResult<Vec<u8>> {
+    fn load_rle_data(w: usize, h: usize, bpp: usize, buf: &mut dyn Read) -> Result<Vec<u8>> {
         let pixelcount = w * h;
-        let mut currentpixel = 0us;
-        let mut currentbyte = 0us;
+        let mut currentpixel = 0usize;
+        let mut currentbyte = 0usize;
 
-        let mut data = Vec::with_capacity(pixelcount * bpp);
-        data.resize(pixelcount * bpp, 0);
+        let mut data = vec![0u8; pixelcount * bpp];
         loop {
-            let mut chunkheader = try!(buf.read_u8());
+            let mut chunkheader = [0u8];
+            buf.read_exact(&mut chunkheader)?;
+            let chunkheader = chunkheader[0];
             if chunkheader < 128 {
-                chunkheader += 1;
-                for _ in range(0u8, chunkheader) {
-                    for __ in range(0us, bpp) {
-                        data[currentbyte] = try!(buf.read_u8());
-                        currentbyte += 1;
-                    }
-                    currentpixel += 1;
-                    if currentpixel > pixelcount {
-                        return Err(IoError{kind: IoErrorKind::InvalidInput, desc: "Too many pixels read", detail: None})
-                    }
+                let count = chunkheader as usize + 1;
+                if currentpixel + count > pixelcount {
+                    return Err(Error::InvalidFormat("Too many pixels read"))
                 }
+                buf.read_exact(&mut data[currentbyte..currentbyte + count * bpp])?;
+                currentbyte += count * bpp;
+                currentpixel += count;
             } else {
-                chunkheader -= 127;
-                let color = try!(Color::from_stream(buf, bpp));
-                for _ in range(0u8, chunkheader) {
-                    for j in range(0us, bpp) {
+                let count = chunkheader as usize - 127;
+                if currentpixel + count > pixelcount {
+                    return Err(Error::InvalidFormat("Too many pixels read"))
+                }
+                let color = Color::from_stream(buf, bpp)?;
+                for _ in 0..count {
+                    for j in 0..bpp {
                         data[currentbyte] = color[j];
                         currentbyte += 1;
                     }
-                    currentpixel += 1;
-                    if currentpixel > pixelcount {
-                        return Err(IoError{kind: IoErrorKind::InvalidInput, desc: "Too many pixels read", detail: None})
-                    }
                 }
+                currentpixel += count;
             }
             if currentpixel == pixelcount {
                 break;
@@ -236,173 +389,210 @@ impl Image {
         Ok(data)
     }
 
-    pub fn write_tga_file(&self, filename: &str, rle: bool) -> IoResult<()> {
-        let developer_area_ref = [0u8, 0u8, 0u8, 0u8];
-        let extension_area_ref = [0u8, 0u8, 0u8, 0u8];
-        let footer = "TRUEVISION-XFILE.\0";
+    pub fn write_tga_file<P: AsRef<Path>>(&self, filename: P, rle: bool) -> Result<()> {
+        let mut f = BufWriter::new(File::create(filename)?);
+        self.write_tga(&mut f, rle)?;
+        f.flush()?;
+        Ok(())
+    }
 
-        let p = Path::new(filename);
-        let mut f = try!(File::create(&p));
+    pub fn write_tga(&self, f: &mut dyn Write, rle: bool) -> Result<()> {
+        let header = Header {
+            bits_per_pixel: (self.bytespp as u8) << 3,
+            width: self.width as i16,
+            height: self.height as i16,
+            data_type_code: match (self.bytespp as u8 == GRAYSCALE, rle) {
+                (true, true) => 11,
+                (true, false) => 3,
+                (false, true) => 10,
+                (false, false) => 2,
+            },
+            image_descriptor: 0x20 | self.alpha_bits(), // top-left origin
+            ..Default::default()
+        };
+        let stamp = self.stamp(|t| convert_pixels(&t.data, t.bytespp, self.bytespp));
+        self.write_tga_parts(f, header, &[], &encode_pixels(&self.data, self.bytespp, rle), stamp)
+    }
 
-        let mut header: Header = Default::default();
-        header.bits_per_pixel = (self.bytespp as u8) << 3;
-        header.width = self.width as i16;
-        header.height = self.height as i16;
-        header.data_type_code = if self.bytespp as u8 == GRAYSCALE {
-            if rle { 11 } else { 3 }
-        } else {
-            if rle { 10 } else { 2 }
+    // Color images with at most 256 distinct colors are written with a color map and 8-bit
+    // indices, anything else falls back to write_tga
+    pub fn write_tga_mapped(&self, f: &mut dyn Write, rle: bool) -> Result<()> {
+        let (entries, indices) = match self.palette() {
+            Some(palette) if self.bytespp != GRAYSCALE as usize => palette,
+            _ => return self.write_tga(f, rle),
         };
-        header.image_descriptor = 0x20; // top-left origin
-        try!(header.to_stream(&mut f));
-        if !rle {
-            try!(f.write_all(self.data.as_slice()));
-        } else {
-            try!(self.dump_rle_data(&mut f));
+        let header = Header {
+            color_map_type: 1,
+            color_map_length: (entries.len() / self.bytespp) as i16,
+            color_map_depth: (self.bytespp as u8) << 3,
+            bits_per_pixel: 8,
+            width: self.width as i16,
+            height: self.height as i16,
+            data_type_code: if rle { 9 } else { 1 },
+            image_descriptor: 0x20 | self.alpha_bits(), // top-left origin
+            ..Default::default()
+        };
+        // The stamp shares the color map, its pixels take the closest entry
+        let stamp = self.stamp(|t| {
+            convert_pixels(&t.data, t.bytespp, self.bytespp).chunks_exact(self.bytespp)
+                .map(|px| closest_entry(&entries, self.bytespp, px)).collect()
+        });
+        self.write_tga_parts(f, header, &entries, &encode_pixels(&indices, 1, rle), stamp)
+    }
+
+    // A1R5G5B5 pixels, channels are truncated to five bits and only RGBA images declare the
+    // attribute bit as alpha
+    pub fn write_tga_16(&self, f: &mut dyn Write, rle: bool) -> Result<()> {
+        let header = Header {
+            bits_per_pixel: 16,
+            width: self.width as i16,
+            height: self.height as i16,
+            data_type_code: if rle { 10 } else { 2 },
+            image_descriptor: 0x20 | if self.bytespp == RGBA as usize { 1 } else { 0 }, // top-left origin
+            ..Default::default()
+        };
+        let pack = |data: &[u8], bytespp: usize| data.chunks_exact(bytespp).flat_map(pack_high_color).collect::<Vec<u8>>();
+        let stamp = self.stamp(|t| pack(&t.data, t.bytespp));
+        self.write_tga_parts(f, header, &[], &encode_pixels(&pack(&self.data, self.bytespp), 2, rle), stamp)
+    }
+
+    // Header, image ID, color map, pixels and the metadata areas
+    fn write_tga_parts(&self, f: &mut dyn Write, mut header: Header, color_map: &[u8], pixels: &[u8],
+                       stamp: Option<(usize, usize, Vec<u8>)>) -> Result<()> {
+        let id = &self.metadata.id[..self.metadata.id.len().min(255)];
+        header.id_length = id.len() as u8;
+        header.to_stream(f)?;
+        f.write_all(id)?;
+        f.write_all(color_map)?;
+        f.write_all(pixels)?;
+        self.metadata.write_areas(f, 18 + id.len() + color_map.len() + pixels.len(), stamp)
+    }
+
+    // Thumbnail pixels top to bottom as encoded by encode, if there is one that fits in a stamp
+    fn stamp(&self, encode: impl Fn(&Image) -> Vec<u8>) -> Option<(usize, usize, Vec<u8>)> {
+        let thumb = self.metadata.extension.as_ref()?.thumbnail.as_ref()?;
+        if thumb.width > 255 || thumb.height > 255 {
+            return None;
         }
-        try!(f.write_all(&developer_area_ref));
-        try!(f.write_all(&extension_area_ref));
-        try!(f.write_all(footer.as_bytes()));
-        Ok(())
+        Some((thumb.width, thumb.height, encode(thumb)))
     }
 
-    fn dump_rle_data(&self, buf: &mut Writer) -> IoResult<()> {
-        const MAX_CHUNK_LENGTH: u8 = 128u8;
-        let npixels = self.width * self.height;
-        let mut curpix = 0us;
-        while curpix < npixels {
-            let chunkstart = curpix * self.bytespp;
-            let mut curbyte = chunkstart;
-            let mut run_length = 1u8;
-            let mut raw = true;
-
-            while (curpix + (run_length as usize) < npixels) && run_length < MAX_CHUNK_LENGTH {
-                let mut succ_eq = true;
-                for t in range(0, self.bytespp) {
-                    succ_eq = self.data[curbyte + t] == self.data[curbyte + t + self.bytespp];
-                    if !succ_eq {
-                        break;
-                    }
-                }
-                curbyte += self.bytespp;
-                if run_length == 1 {
-                    raw = !succ_eq;
-                }
-                if raw && succ_eq {
-                    run_length -= 1;
-                    break;
-                }
-                if !raw && !succ_eq {
-                    break;
-                }
-                run_length += 1;
-            }
-            curpix += run_length as usize;
-            try!(buf.write_u8(if raw { run_length - 1 } else { run_length + 127 }));
-            try!(buf.write_all(&self.data
-                           .as_slice()
-                           [chunkstart..]
-                           [..if raw { (run_length as usize) * self.bytespp } else { self.bytespp }]
-                          ));
+    fn alpha_bits(&self) -> u8 {
+        if self.bytespp == RGBA as usize { 8 } else { 0 }
+    }
+
+    // Distinct colors in order of appearance and the index of every pixel, None past 256 colors
+    fn palette(&self) -> Option<(Vec<u8>, Vec<u8>)> {
+        let mut lookup: HashMap<&[u8], u8> = HashMap::new();
+        let mut entries = Vec::new();
+        let mut indices = Vec::with_capacity(self.width * self.height);
+        for px in self.data.chunks_exact(self.bytespp) {
+            let next = lookup.len();
+            let index = match lookup.get(px) {
+                Some(&i) => i,
+                None if next < 256 => {
+                    lookup.insert(px, next as u8);
+                    entries.extend_from_slice(px);
+                    next as u8
+                },
+                None => return None,
+            };
+            indices.push(index);
         }
-        Ok(())
+        Some((entries, indices))
+    }
+
+    #[inline]
+    pub fn bytespp(&self) -> usize {
+        self.bytespp
     }
 
     pub fn new(w: usize, h: usize, bpp: usize) -> Self {
-        let mut data = Vec::with_capacity(w * h * bpp);
-        data.resize(w * h * bpp, 0);
-        Image {data: data, width: w, height: h, bytespp: bpp}
+        Image::from_data(vec![0u8; w * h * bpp], w, h, bpp)
     }
 
-    pub fn flip_horizontally(&mut self) -> IoResult<()> {
+    fn from_data(data: Vec<u8>, w: usize, h: usize, bpp: usize) -> Self {
+        Image {data, width: w, height: h, bytespp: bpp, metadata: TgaMetadata::default()}
+    }
+
+    pub fn flip_horizontally(&mut self) -> Result<()> {
         let half: usize = self.width >> 1;
         let w = self.width;
-        for i in range(0us, half) {
-            for j in range(0us, self.height) {
-                let c1 = try!(self.get(i, j));
-                let c2 = try!(self.get(w - 1 - i, j));
-                try!(self.set(i, j, &c2));
-                try!(self.set(w - 1 - i, j, &c1));
+        for i in 0..half {
+            for j in 0..self.height {
+                let c1 = self.get(i, j)?;
+                let c2 = self.get(w - 1 - i, j)?;
+                self.set(i, j, &c2)?;
+                self.set(w - 1 - i, j, &c1)?;
             }
         }
         Ok(())
     }
 
-    pub fn flip_vertically(&mut self) -> IoResult<()> {
+    pub fn flip_vertically(&mut self) -> Result<()> {
         let half: usize = self.height >> 1;
         let bytes_per_line = self.width * self.bytespp;
-        let mut line = Vec::with_capacity(bytes_per_line);
-        line.resize(bytes_per_line, 0u8);
 
-        for j in range(0us, half) {
-            let l1 = j * bytes_per_line;
+        for j in 0..half {
             let l2 = (self.height - 1 - j) * bytes_per_line;
-            unsafe {
-                copy_memory(line.as_mut_ptr(), self.data.as_ptr().offset(l1 as isize), bytes_per_line);
-                copy_memory(self.data.as_mut_ptr().offset(l1 as isize), self.data.as_ptr().offset(l2 as isize), bytes_per_line);
-                copy_memory(self.data.as_mut_ptr().offset(l2 as isize), line.as_ptr(), bytes_per_line);
-            }
+            let (top, bottom) = self.data.split_at_mut(l2);
+            let l1 = j * bytes_per_line;
+            top[l1..l1 + bytes_per_line].swap_with_slice(&mut bottom[..bytes_per_line]);
         }
         Ok(())
     }
 
-    pub fn get(&self, x: usize, y: usize) -> IoResult<Color> {
+    pub fn get(&self, x: usize, y: usize) -> Result<Color> {
         if x >= self.width || y >= self.height {
-            return Err(IoError{kind: IoErrorKind::InvalidInput, desc: "Wrong coords", detail: None})
+            return Err(Error::WrongCoords)
         }
         let start = (x + y * self.width) * self.bytespp;
-        let bytes = &self.data.as_slice()[start .. start + self.bytespp];
+        let bytes = &self.data[start .. start + self.bytespp];
         Ok(Color::from_raw(bytes, self.bytespp))
     }
 
-    pub fn set(&mut self, x: usize, y: usize, c: &Color) -> IoResult<()> {
+    pub fn set(&mut self, x: usize, y: usize, c: &Color) -> Result<()> {
         if x >= self.width || y >= self.height {
-            return Err(IoError{kind: IoErrorKind::InvalidInput, desc: "Wrong coords", detail: None})
+            return Err(Error::WrongCoords)
         }
         let start = (x + y * self.width) * self.bytespp;
-        unsafe
-        {
-            let cc: &u8 = transmute(c);
-            copy_nonoverlapping_memory(self.data.as_mut_ptr().offset(start as isize), &*cc as *const u8, self.bytespp);
+        for i in 0..self.bytespp {
+            self.data[start + i] = c[i];
         }
         Ok(())
     }
 
-    pub fn scale(&mut self, w: usize, h: usize) -> IoResult<()> {
-        let mut newdata = Vec::with_capacity(w * h * self.bytespp);
-        newdata.resize(w * h * self.bytespp, 0u8);
+    pub fn scale(&mut self, w: usize, h: usize) -> Result<()> {
+        let mut newdata = vec![0u8; w * h * self.bytespp];
 
-        let mut nscanline = 0is;
-        let mut oscanline = 0is;
-        let mut erry = 0is;
+        let mut nscanline = 0usize;
+        let mut oscanline = 0usize;
+        let mut erry = 0isize;
         let nlinebytes = w * self.bytespp;
-        let olinebytes = (self.width * self.bytespp) as isize;
+        let olinebytes = self.width * self.bytespp;
 
-        for _ in range(0, self.height) {
+        for _ in 0..self.height {
             let mut errx = (self.width as isize) - (w as isize);
-            let mut nx = -(self.bytespp as isize);
-            let mut ox = -(self.bytespp as isize);
-            for __ in range(0, self.width) {
-                ox += self.bytespp as isize;
+            let mut nx = 0usize;
+            let mut ox = 0usize;
+            for _ in 0..self.width {
                 errx += w as isize;
                 while errx >= self.width as isize {
                     errx -= self.width as isize;
-                    nx += self.bytespp as isize;
-                    unsafe {
-                        copy_nonoverlapping_memory(newdata.as_mut_ptr().offset(nscanline + nx), self.data.as_ptr().offset(oscanline + ox), self.bytespp)
-                    }
+                    newdata[nscanline + nx..nscanline + nx + self.bytespp]
+                        .copy_from_slice(&self.data[oscanline + ox..oscanline + ox + self.bytespp]);
+                    nx += self.bytespp;
                 }
+                ox += self.bytespp;
             }
             erry += h as isize;
             oscanline += olinebytes;
             while erry >= self.height as isize {
                 if erry >= ((self.height as isize) << 1) {
-                    unsafe {
-                        copy_nonoverlapping_memory(newdata.as_mut_ptr().offset(nscanline + nlinebytes as isize), newdata.as_ptr().offset(nscanline), nlinebytes);
-                    }
+                    newdata.copy_within(nscanline..nscanline + nlinebytes, nscanline + nlinebytes);
                 }
                 erry -= self.height as isize;
-                nscanline += nlinebytes as isize;
+                nscanline += nlinebytes;
             }
         }
         self.data = newdata;
@@ -412,8 +602,163 @@ impl Image {
     }
 
     pub fn clear(&mut self) {
-        unsafe {
-            set_memory(self.data.as_mut_slice().as_mut_ptr(), 0u8, self.data.len())
+        for b in self.data.iter_mut() {
+            *b = 0;
         }
     }
+
+    pub fn fill(&mut self, c: &Color) {
+        for pixel in self.data.chunks_mut(self.bytespp) {
+            for (i, b) in pixel.iter_mut().enumerate() {
+                *b = c[i];
+            }
+        }
+    }
+}
+
+fn encode_pixels(data: &[u8], bytespp: usize, rle: bool) -> Vec<u8> {
+    if !rle {
+        return data.to_vec();
+    }
+    let mut out = Vec::new();
+    dump_rle_data(&mut out, data, bytespp);
+    out
 }
+
+fn dump_rle_data(buf: &mut Vec<u8>, data: &[u8], bytespp: usize) {
+    const MAX_CHUNK_LENGTH: u8 = 128u8;
+    let npixels = data.len() / bytespp;
+    let mut curpix = 0usize;
+    while curpix < npixels {
+        let chunkstart = curpix * bytespp;
+        let mut curbyte = chunkstart;
+        let mut run_length = 1u8;
+        let mut raw = true;
+
+        while (curpix + (run_length as usize) < npixels) && run_length < MAX_CHUNK_LENGTH {
+            let succ_eq = data[curbyte..curbyte + bytespp] ==
+                data[curbyte + bytespp..curbyte + 2 * bytespp];
+            curbyte += bytespp;
+            if run_length == 1 {
+                raw = !succ_eq;
+            }
+            if raw && succ_eq {
+                run_length -= 1;
+                break;
+            }
+            if !raw && !succ_eq {
+                break;
+            }
+            run_length += 1;
+        }
+        curpix += run_length as usize;
+        buf.push(if raw { run_length - 1 } else { run_length + 127 });
+        buf.extend_from_slice(&data
+                              [chunkstart..]
+                              [..if raw { (run_length as usize) * bytespp } else { bytespp }]
+                             );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Image;
+
+    // count distinct colors repeated over the image, alpha varies with them
+    fn image(w: usize, h: usize, bytespp: usize, count: usize) -> Image {
+        let mut image = Image::new(w, h, bytespp);
+        for (i, px) in image.data.chunks_exact_mut(bytespp).enumerate() {
+            let c = i * 7 % count;
+            px.copy_from_slice(&[c as u8, (c * 3) as u8, (c / 2) as u8, !(c as u8)][..bytespp]);
+        }
+        image
+    }
+
+    fn read(data: &[u8]) -> Image {
+        Image::read_tga(&mut &data[..]).unwrap()
+    }
+
+    #[test]
+    fn mapped_round_trip() {
+        for bytespp in [3, 4] {
+            for count in [1, 2, 255, 256] {
+                let original = image(19, 17, bytespp, count);
+                for rle in [false, true] {
+                    let mut out = Vec::new();
+                    original.write_tga_mapped(&mut out, rle).unwrap();
+                    // Color map with 8-bit indices
+                    assert_eq!(out[1..3], [1, if rle { 9 } else { 1 }]);
+                    assert_eq!(u16::from_le_bytes([out[5], out[6]]) as usize, count);
+                    assert_eq!((out[7], out[16]), (bytespp as u8 * 8, 8));
+                    let copy = read(&out);
+                    assert_eq!(copy.bytespp(), bytespp);
+                    assert!(copy.data == original.data, "bytespp {} count {} rle {}", bytespp, count, rle);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn mapped_fallback() {
+        // Too many colors and grayscale are written as true color
+        for (bytespp, count) in [(3, 257), (1, 10)] {
+            let original = image(19, 17, bytespp, count);
+            let mut out = Vec::new();
+            original.write_tga_mapped(&mut out, true).unwrap();
+            assert_eq!(out[1], 0);
+            assert!(read(&out).data == original.data);
+        }
+    }
+
+    // Header of an uncompressed top-left true-color image
+    fn header(w: u8, h: u8, bits: u8, descriptor: u8) -> Vec<u8> {
+        vec![0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, w, 0, h, 0, bits, 0x20 | descriptor]
+    }
+
+    #[test]
+    fn high_color_round_trip() {
+        // Channels keep their top five bits, replicated into the low ones, alpha becomes one bit
+        let five_bits = |v: u8| (v & 0xf8) | (v >> 5);
+        for bytespp in [1, 3, 4] {
+            let original = image(19, 17, bytespp, 300);
+            for rle in [false, true] {
+                let mut out = Vec::new();
+                original.write_tga_16(&mut out, rle).unwrap();
+                assert_eq!((out[16], out[17] & 0x0f), (16, if bytespp == 4 { 1 } else { 0 }));
+                let copy = read(&out);
+                assert_eq!(copy.bytespp(), if bytespp == 4 { 4 } else { 3 });
+                for (px, src) in copy.data.chunks_exact(copy.bytespp()).zip(original.data.chunks_exact(bytespp)) {
+                    let bgr = if bytespp == 1 { [src[0]; 3] } else { [src[0], src[1], src[2]] };
+                    assert_eq!(px[..3], bgr.map(five_bits));
+                    if bytespp == 4 {
+                        assert_eq!(px[3], if src[3] >= 128 { 255 } else { 0 });
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn attribute_bits() {
+        // 32-bit pixels are only translucent when the descriptor declares alpha bits
+        let mut file = header(2, 1, 32, 0);
+        file.extend([1, 2, 3, 0, 4, 5, 6, 128]);
+        assert_eq!(read(&file).data, [1, 2, 3, 255, 4, 5, 6, 255]);
+        file[17] |= 8;
+        assert_eq!(read(&file).data, [1, 2, 3, 0, 4, 5, 6, 128]);
+
+        // The top bit of 15-bit pixels and of 16-bit pixels without alpha bits is ignored
+        let pixels = [0x1f, 0x80, 0xe0, 0x03];
+        for (bits, descriptor) in [(15, 0), (16, 0), (15, 1)] {
+            let mut file = header(2, 1, bits, descriptor);
+            file.extend(pixels);
+            let image = read(&file);
+            assert_eq!(image.bytespp(), 3);
+            assert_eq!(image.data, [255, 0, 0, 0, 255, 0]);
+        }
+        let mut file = header(2, 1, 16, 1);
+        file.extend(pixels);
+        assert_eq!(read(&file).data, [255, 0, 0, 255, 0, 255, 0, 0]);
+    }
+}
+
@@ -0,0 +1,335 @@
+use std::io::Write;
+use crate::error::Result;
+use crate::tgaimage::Image;
+
+const EXTENSION_SIZE: usize = 495;
+const SIGNATURE: &[u8; 18] = b"TRUEVISION-XFILE.\0";
+
+// Everything a TGA file carries besides its pixels
+#[derive(PartialEq, Clone, Default)]
+pub struct TgaMetadata {
+    pub id: Vec<u8>,
+    pub extension: Option<TgaExtension>,
+    pub developer: Vec<TgaTag>,
+}
+
+#[derive(PartialEq, Clone, Default)]
+pub struct TgaExtension {
+    pub author: String,
+    pub comments: [String; 4],
+    pub timestamp: Option<TgaTimestamp>,
+    pub job_name: String,
+    // Hours, minutes and seconds
+    pub job_time: (u16, u16, u16),
+    pub software: String,
+    // Version times 100 and a letter, e.g. (410, b'b') for 4.10b
+    pub software_version: (u16, u8),
+    // Background color as A8R8G8B8
+    pub key_color: u32,
+    pub pixel_aspect: (u16, u16),
+    // Numerator and denominator, (0, 0) when unset
+    pub gamma: (u16, u16),
+    // 256 entries of A, R, G and B, 16 bits each
+    pub color_correction: Option<Vec<u16>>,
+    // Postage stamp, stored in the pixel format of the image it belongs to
+    pub thumbnail: Option<Box<Image>>,
+    // 0: no alpha, 1 and 2: undefined data to ignore or keep, 3: alpha, 4: premultiplied alpha
+    pub attribute_type: u8,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub struct TgaTimestamp {
+    pub year: u16,
+    pub month: u16,
+    pub day: u16,
+    pub hour: u16,
+    pub minute: u16,
+    pub second: u16,
+}
+
+// Application-defined data of the developer area
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct TgaTag {
+    pub tag: u16,
+    pub data: Vec<u8>,
+}
+
+impl TgaExtension {
+    pub fn gamma(&self) -> Option<f32> {
+        match self.gamma {
+            (_, 0) => None,
+            (n, d) => Some(n as f32 / d as f32),
+        }
+    }
+}
+
+fn u16_at(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([data[pos], data[pos + 1]])
+}
+
+fn u32_at(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+}
+
+// Fixed-size, NUL-terminated ASCII field
+fn string_at(data: &[u8], pos: usize, size: usize) -> String {
+    let field = &data[pos..pos + size];
+    let end = field.iter().position(|&c| c == 0).unwrap_or(size);
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+fn put_string(out: &mut Vec<u8>, s: &str, size: usize) {
+    let bytes = &s.as_bytes()[..s.len().min(size - 1)];
+    out.extend_from_slice(bytes);
+    out.resize(out.len() + size - bytes.len(), 0);
+}
+
+fn area(file: &[u8], offset: usize, size: usize) -> Option<&[u8]> {
+    file.get(offset..offset.checked_add(size)?)
+}
+
+// Extension and developer areas of a whole TGA file. The postage stamp is handed to
+// decode_stamp as its width, height and raw pixels of stamp_bytespp bytes each.
+// Offsets or sizes pointing outside the file drop the part they belong to, the pixels are
+// still good.
+pub(crate) fn read_areas(file: &[u8], stamp_bytespp: usize,
+                         decode_stamp: &dyn Fn(Vec<u8>, usize, usize) -> Result<Image>)
+                         -> (Option<TgaExtension>, Vec<TgaTag>) {
+    // TGA 1.0 files end with their pixels
+    if file.len() < 26 || &file[file.len() - 18..] != SIGNATURE {
+        return (None, Vec::new());
+    }
+    let footer = file.len() - 26;
+    let ext_offset = u32_at(file, footer) as usize;
+    let dev_offset = u32_at(file, footer + 4) as usize;
+    let developer = match dev_offset {
+        0 => Vec::new(),
+        _ => read_developer(file, dev_offset).unwrap_or_default(),
+    };
+    let extension = match ext_offset {
+        0 => None,
+        _ => read_extension(file, ext_offset, stamp_bytespp, decode_stamp),
+    };
+    (extension, developer)
+}
+
+fn read_developer(file: &[u8], offset: usize) -> Option<Vec<TgaTag>> {
+    let count = u16_at(area(file, offset, 2)?, 0) as usize;
+    let dir = area(file, offset + 2, count * 10)?;
+    Some(dir.chunks_exact(10).filter_map(|entry| {
+        let (offset, size) = (u32_at(entry, 2) as usize, u32_at(entry, 6) as usize);
+        Some(TgaTag {tag: u16_at(entry, 0), data: area(file, offset, size)?.to_vec()})
+    }).collect())
+}
+
+fn read_extension(file: &[u8], offset: usize, stamp_bytespp: usize,
+                  decode_stamp: &dyn Fn(Vec<u8>, usize, usize) -> Result<Image>) -> Option<TgaExtension> {
+    let ext = area(file, offset, EXTENSION_SIZE)?;
+    if (u16_at(ext, 0) as usize) < EXTENSION_SIZE {
+        return None;
+    }
+    let date = |i: usize| u16_at(ext, 367 + 2 * i);
+    let timestamp = TgaTimestamp {month: date(0), day: date(1), year: date(2), hour: date(3), minute: date(4), second: date(5)};
+
+    let color_correction = match u32_at(ext, 482) as usize {
+        0 => None,
+        cc_offset => area(file, cc_offset, 2048)
+            .map(|table| table.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect()),
+    };
+    let thumbnail = match u32_at(ext, 486) as usize {
+        0 => None,
+        stamp_offset => area(file, stamp_offset, 2).and_then(|dims| {
+            let (w, h) = (dims[0] as usize, dims[1] as usize);
+            let pixels = area(file, stamp_offset + 2, w * h * stamp_bytespp)?;
+            if w == 0 || h == 0 { None } else { decode_stamp(pixels.to_vec(), w, h).ok().map(Box::new) }
+        }),
+    };
+
+    // The scan line table only holds offsets into this particular file and is not kept
+    Some(TgaExtension {
+        author: string_at(ext, 2, 41),
+        comments: [0, 1, 2, 3].map(|i| string_at(ext, 43 + 81 * i, 81)),
+        timestamp: if timestamp == TgaTimestamp::default() { None } else { Some(timestamp) },
+        job_name: string_at(ext, 379, 41),
+        job_time: (u16_at(ext, 420), u16_at(ext, 422), u16_at(ext, 424)),
+        software: string_at(ext, 426, 41),
+        software_version: (u16_at(ext, 467), ext[469]),
+        key_color: u32_at(ext, 470),
+        pixel_aspect: (u16_at(ext, 474), u16_at(ext, 476)),
+        gamma: (u16_at(ext, 478), u16_at(ext, 480)),
+        color_correction,
+        thumbnail,
+        attribute_type: ext[494],
+    })
+}
+
+impl TgaMetadata {
+    // Writes the developer area, the extension area with its tables and the footer. offset is the
+    // number of bytes already written, stamp the width, height and encoded pixels of the thumbnail.
+    pub(crate) fn write_areas(&self, f: &mut dyn Write, offset: usize, stamp: Option<(usize, usize, Vec<u8>)>) -> Result<()> {
+        let mut out = Vec::new();
+
+        let mut dev_offset = 0;
+        if !self.developer.is_empty() {
+            let mut dir = (self.developer.len() as u16).to_le_bytes().to_vec();
+            for tag in &self.developer {
+                dir.extend(tag.tag.to_le_bytes());
+                dir.extend(((offset + out.len()) as u32).to_le_bytes());
+                dir.extend((tag.data.len() as u32).to_le_bytes());
+                out.extend_from_slice(&tag.data);
+            }
+            dev_offset = offset + out.len();
+            out.extend(dir);
+        }
+
+        let mut ext_offset = 0;
+        if let Some(ref ext) = self.extension {
+            ext_offset = offset + out.len();
+            let cc_offset = match ext.color_correction {
+                Some(_) => ext_offset + EXTENSION_SIZE,
+                None => 0,
+            };
+            let stamp_offset = match stamp {
+                Some(_) => ext_offset + EXTENSION_SIZE + if cc_offset != 0 { 2048 } else { 0 },
+                None => 0,
+            };
+
+            out.extend((EXTENSION_SIZE as u16).to_le_bytes());
+            put_string(&mut out, &ext.author, 41);
+            for comment in &ext.comments {
+                put_string(&mut out, comment, 81);
+            }
+            let t = ext.timestamp.unwrap_or_default();
+            for v in [t.month, t.day, t.year, t.hour, t.minute, t.second] {
+                out.extend(v.to_le_bytes());
+            }
+            put_string(&mut out, &ext.job_name, 41);
+            for v in [ext.job_time.0, ext.job_time.1, ext.job_time.2] {
+                out.extend(v.to_le_bytes());
+            }
+            put_string(&mut out, &ext.software, 41);
+            out.extend(ext.software_version.0.to_le_bytes());
+            out.push(ext.software_version.1);
+            out.extend(ext.key_color.to_le_bytes());
+            for v in [ext.pixel_aspect.0, ext.pixel_aspect.1, ext.gamma.0, ext.gamma.1] {
+                out.extend(v.to_le_bytes());
+            }
+            for v in [cc_offset, stamp_offset, 0] {
+                out.extend((v as u32).to_le_bytes());
+            }
+            out.push(ext.attribute_type);
+
+            if let Some(ref table) = ext.color_correction {
+                let mut table = table.clone();
+                table.resize(1024, 0);
+                out.extend(table.iter().flat_map(|v| v.to_le_bytes()));
+            }
+            if let Some((w, h, pixels)) = stamp {
+                out.extend([w as u8, h as u8]);
+                out.extend(pixels);
+            }
+        }
+
+        f.write_all(&out)?;
+        f.write_all(&(ext_offset as u32).to_le_bytes())?;
+        f.write_all(&(dev_offset as u32).to_le_bytes())?;
+        f.write_all(SIGNATURE)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TgaExtension, TgaMetadata, TgaTag, TgaTimestamp};
+    use crate::tgaimage::Image;
+
+    // Channels that survive five bits and alpha that survives one, so every writer keeps them
+    const COLORS: [[u8; 4]; 3] = [[0, 0, 0, 0], [255, 132, 8, 255], [66, 255, 173, 255]];
+
+    fn image(w: usize, h: usize, bytespp: usize) -> Image {
+        let mut image = Image::new(w, h, bytespp);
+        for (i, px) in image.data.chunks_exact_mut(bytespp).enumerate() {
+            px.copy_from_slice(&COLORS[i * 7 % 5 % 3][..bytespp]);
+        }
+        image
+    }
+
+    fn metadata(bytespp: usize) -> TgaMetadata {
+        TgaMetadata {
+            id: b"test image".to_vec(),
+            extension: Some(TgaExtension {
+                author: "Author".to_string(),
+                comments: ["first".to_string(), String::new(), "third".to_string(), "x".repeat(80)],
+                timestamp: Some(TgaTimestamp {year: 2024, month: 2, day: 29, hour: 23, minute: 59, second: 58}),
+                job_name: "Job".to_string(),
+                job_time: (1, 2, 3),
+                software: "Software".to_string(),
+                software_version: (410, b'b'),
+                key_color: 0xff102030,
+                pixel_aspect: (4, 3),
+                gamma: (22, 10),
+                color_correction: Some((0..1024).map(|i| (i * 64) as u16).collect()),
+                thumbnail: Some(Box::new(image(3, 2, bytespp))),
+                attribute_type: if bytespp == 4 { 3 } else { 0 },
+            }),
+            developer: vec![TgaTag {tag: 7, data: vec![1, 2, 3]}, TgaTag {tag: 40000, data: Vec::new()}],
+        }
+    }
+
+    fn write(image: &Image, writer: usize, rle: bool) -> Vec<u8> {
+        let mut out = Vec::new();
+        match writer {
+            0 => image.write_tga(&mut out, rle),
+            1 => image.write_tga_mapped(&mut out, rle),
+            _ => image.write_tga_16(&mut out, rle),
+        }.unwrap();
+        out
+    }
+
+    #[test]
+    fn metadata_round_trip() {
+        for bytespp in [3, 4] {
+            let mut original = image(5, 4, bytespp);
+            original.metadata = metadata(bytespp);
+            for writer in 0..3 {
+                for rle in [false, true] {
+                    let copy = Image::read_tga(&mut &write(&original, writer, rle)[..]).unwrap();
+                    assert!(copy.data == original.data, "pixels, writer {} rle {} bytespp {}", writer, rle, bytespp);
+                    assert!(copy.metadata == original.metadata, "metadata, writer {} rle {} bytespp {}", writer, rle, bytespp);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn corrupt_areas() {
+        let mut original = image(5, 4, 3);
+        original.metadata = metadata(3);
+        let file = write(&original, 0, true);
+        let footer = file.len() - 26;
+        let read = |patch: &dyn Fn(&mut Vec<u8>)| {
+            let mut file = file.clone();
+            patch(&mut file);
+            let copy = Image::read_tga(&mut &file[..]).unwrap();
+            assert!(copy.data == original.data);
+            copy.metadata
+        };
+
+        // Offsets past the end drop their area only
+        let meta = read(&|f| f[footer..footer + 4].copy_from_slice(&u32::MAX.to_le_bytes()));
+        assert!(meta.extension.is_none());
+        assert_eq!(meta.developer, original.metadata.developer);
+        let meta = read(&|f| f[footer + 4..footer + 8].copy_from_slice(&(footer as u32 - 1).to_le_bytes()));
+        assert!(meta.developer.is_empty());
+        assert!(meta.extension == original.metadata.extension);
+
+        // A damaged signature leaves a TGA 1.0 file, the image ID is part of the header
+        let meta = read(&|f| f[footer + 8] = b'X');
+        assert!(meta.extension.is_none() && meta.developer.is_empty());
+        assert_eq!(meta.id, original.metadata.id);
+
+        // A truncated file loses its footer along with everything after the pixels
+        let meta = read(&|f| f.truncate(footer + 10));
+        assert!(meta.extension.is_none() && meta.developer.is_empty());
+    }
+}
@@ -0,0 +1,79 @@
+use crate::vec::{Vec2, Vec2f, Vec3, Vec3f};
+
+// Newell's method, works for concave and slightly non-planar polygons
+fn polygon_normal(pts: &[Vec3f]) -> Vec3f {
+    let mut n = Vec3::new(0., 0., 0.);
+    for i in 0..pts.len() {
+        let (a, b) = (&pts[i], &pts[(i + 1) % pts.len()]);
+        n.x += (a.y - b.y) * (a.z + b.z);
+        n.y += (a.z - b.z) * (a.x + b.x);
+        n.z += (a.x - b.x) * (a.y + b.y);
+    }
+    n
+}
+
+// Drops the dominant axis of the normal, keeping the winding counter-clockwise
+fn project(pts: &[Vec3f]) -> Vec<Vec2f> {
+    let n = polygon_normal(pts);
+    let (ax, ay, az) = (n.x.abs(), n.y.abs(), n.z.abs());
+    pts.iter().map(|p| {
+        if az >= ax && az >= ay {
+            if n.z >= 0. { Vec2::new(p.x, p.y) } else { Vec2::new(p.y, p.x) }
+        } else if ax >= ay {
+            if n.x >= 0. { Vec2::new(p.y, p.z) } else { Vec2::new(p.z, p.y) }
+        } else if n.y >= 0. {
+            Vec2::new(p.z, p.x)
+        } else {
+            Vec2::new(p.x, p.z)
+        }
+    }).collect()
+}
+
+#[inline]
+fn cross(a: &Vec2f, b: &Vec2f, c: &Vec2f) -> f32 {
+    (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x)
+}
+
+fn inside(p: &Vec2f, a: &Vec2f, b: &Vec2f, c: &Vec2f) -> bool {
+    cross(a, b, p) >= 0. && cross(b, c, p) >= 0. && cross(c, a, p) >= 0.
+}
+
+fn fan(indices: &[usize]) -> Vec<[usize; 3]> {
+    (1..indices.len().saturating_sub(1)).map(|i| [indices[0], indices[i], indices[i + 1]]).collect()
+}
+
+// Splits a simple polygon into triangles given as indices into pts, keeping its winding.
+// Convex polygons are fanned, concave ones go through ear clipping.
+pub fn triangulate(pts: &[Vec3f]) -> Vec<[usize; 3]> {
+    let n = pts.len();
+    let all: Vec<usize> = (0..n).collect();
+    if n <= 3 {
+        return fan(&all);
+    }
+    let p = project(pts);
+    let convex = (0..n).all(|i| cross(&p[(i + n - 1) % n], &p[i], &p[(i + 1) % n]) >= 0.);
+    if convex {
+        return fan(&all);
+    }
+
+    let mut left = all;
+    let mut result = Vec::with_capacity(n - 2);
+    while left.len() > 3 {
+        let m = left.len();
+        let ear = (0..m).find(|&i| {
+            let (a, b, c) = (left[(i + m - 1) % m], left[i], left[(i + 1) % m]);
+            cross(&p[a], &p[b], &p[c]) > 0. &&
+                !left.iter().any(|&j| j != a && j != b && j != c && inside(&p[j], &p[a], &p[b], &p[c]))
+        });
+        match ear {
+            Some(i) => {
+                result.push([left[(i + m - 1) % m], left[i], left[(i + 1) % m]]);
+                left.remove(i);
+            },
+            // Self-intersecting or degenerate leftovers, fan them rather than lose them
+            None => break,
+        }
+    }
+    result.extend(fan(&left));
+    result
+}
@@ -1,185 +1,391 @@
-use std::num::{Float, FromPrimitive, ToPrimitive, NumCast};
-use std::ops::{Add, Sub, Mul, BitXor};
-use std::clone::Clone;
+use std::ops::{Add, Sub, Mul, BitXor, Index, IndexMut};
 
-#[derive(Clone, Debug)]
-pub struct Vec2<T> where T: Clone + NumCast + Mul + Add + Sub {
+pub trait Scalar: Copy + PartialOrd + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> {
+    fn from_f64(v: f64) -> Self;
+    fn to_f64(self) -> f64;
+
+    #[inline]
+    fn from_f32(v: f32) -> Self {
+        Self::from_f64(v as f64)
+    }
+
+    #[inline]
+    fn to_f32(self) -> f32 {
+        self.to_f64() as f32
+    }
+
+    #[inline]
+    fn cast<K: Scalar>(self) -> K {
+        K::from_f64(self.to_f64())
+    }
+}
+
+macro_rules! impl_scalar (
+    ($($t:ty),*) => (
+        $(
+            impl Scalar for $t {
+                #[inline]
+                fn from_f64(v: f64) -> $t { v as $t }
+                #[inline]
+                fn to_f64(self) -> f64 { self as f64 }
+            }
+        )*
+    )
+);
+
+impl_scalar!(f32, f64, i32, i64, isize, usize);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Vec2<T> {
     pub x: T,
     pub y: T
 }
 
-impl<T: Copy> Copy for Vec2<T> {}
-
-impl<T: Copy + Clone + NumCast + Mul + Add + Sub> Vec2<T>
-where <T as Mul>::Output: NumCast + Mul,
-<<T as Mul>::Output as Mul>::Output: ToPrimitive {
+impl<T: Scalar> Vec2<T> {
     pub fn new(x: T, y: T) -> Vec2<T> {
-        Vec2 {x: x, y: y}
+        Vec2 {x, y}
     }
 
     pub fn scale(&mut self, width: T, height: T) {
-        self.x = NumCast::from(self.x * width * NumCast::from(0.5f32).unwrap()).unwrap();
-        self.y = NumCast::from(self.y * height * NumCast::from(0.5f32).unwrap()).unwrap();
+        self.x = T::from_f64((self.x * width).to_f64() * 0.5);
+        self.y = T::from_f64((self.y * height).to_f64() * 0.5);
     }
 
-    pub fn to<K: Clone + NumCast + Mul + Add + Sub>(&self) -> Vec2<K> {
+    pub fn to<K: Scalar>(&self) -> Vec2<K> {
         Vec2 {
-            x: NumCast::from(self.x).unwrap(),
-            y: NumCast::from(self.y).unwrap()
+            x: self.x.cast(),
+            y: self.y.cast()
         }
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Vec3<T> {
     pub x: T,
     pub y: T,
     pub z: T,
 }
 
-impl<T: Copy> Copy for Vec3<T> {}
-
-impl<T: Clone + NumCast + Mul + Add + Sub> Add for Vec2<T>
-where <T as Add>::Output: Clone + NumCast + Mul + Add + Sub {
-    type Output = Vec2<<T as Add<T>>::Output>;
-    fn add(self, rhs: Vec2<T>) -> Vec2<<T as Add<T>>::Output> {
+impl<T: Add<Output = T>> Add for Vec2<T> {
+    type Output = Vec2<T>;
+    fn add(self, rhs: Vec2<T>) -> Vec2<T> {
         Vec2{x: self.x + rhs.x, y: self.y + rhs.y}
     }
 }
 
-impl<T: Add<T>> Add for Vec3<T> {
-    type Output = Vec3<<T as Add<T>>::Output>;
-    fn add(self, rhs: Vec3<T>) -> Vec3<<T as Add<T>>::Output> {
+impl<T: Add<Output = T>> Add for Vec3<T> {
+    type Output = Vec3<T>;
+    fn add(self, rhs: Vec3<T>) -> Vec3<T> {
         Vec3{x: self.x + rhs.x, y: self.y + rhs.y, z: self.z + rhs.z}
     }
 }
 
-impl<'a, T: Copy + Add<T>> Add for &'a Vec3<T> {
-    type Output = Vec3<<T as Add<T>>::Output>;
-    fn add(self, rhs: &Vec3<T>) -> Vec3<<T as Add<T>>::Output> {
+impl<T: Copy + Add<Output = T>> Add for &Vec3<T> {
+    type Output = Vec3<T>;
+    fn add(self, rhs: &Vec3<T>) -> Vec3<T> {
         Vec3{x: self.x + rhs.x, y: self.y + rhs.y, z: self.z + rhs.z}
     }
 }
 
-impl<T: Clone + NumCast + Mul + Add + Sub> Sub for Vec2<T>
-where <T as Sub>::Output: Clone + NumCast + Mul + Add + Sub {
-    type Output = Vec2<<T as Sub<T>>::Output>;
-    fn sub(self, rhs: Vec2<T>) -> Vec2<<T as Sub<T>>::Output> {
+impl<T: Sub<Output = T>> Sub for Vec2<T> {
+    type Output = Vec2<T>;
+    fn sub(self, rhs: Vec2<T>) -> Vec2<T> {
         Vec2{x: self.x - rhs.x, y: self.y - rhs.y}
     }
 }
 
-impl<T: Sub> Sub for Vec3<T> {
-    type Output = Vec3<<T as Sub<T>>::Output>;
-    fn sub(self, rhs: Vec3<T>) -> Vec3<<T as Sub<T>>::Output> {
+impl<T: Sub<Output = T>> Sub for Vec3<T> {
+    type Output = Vec3<T>;
+    fn sub(self, rhs: Vec3<T>) -> Vec3<T> {
         Vec3{x: self.x - rhs.x, y: self.y - rhs.y, z: self.z - rhs.z}
     }
 }
 
-impl<T: Clone + NumCast + Mul + Add + Sub + ToPrimitive + FromPrimitive> Mul<f32> for Vec2<T> {
+impl<T: Scalar> Mul<f32> for Vec2<T> {
     type Output = Vec2<T>;
     fn mul(self, rhs: f32) -> Vec2<T> {
         Vec2{
-            x: FromPrimitive::from_f32(rhs * self.x.to_f32().unwrap()).unwrap(),
-            y: FromPrimitive::from_f32(rhs * self.y.to_f32().unwrap()).unwrap(),
+            x: T::from_f32(rhs * self.x.to_f32()),
+            y: T::from_f32(rhs * self.y.to_f32()),
         }
     }
 }
 
-impl<T: Mul + FromPrimitive + ToPrimitive> Mul<f32> for Vec3<T> where <T as Mul>::Output: FromPrimitive {
-    type Output = Vec3<<T as Mul<T>>::Output>;
-    fn mul(self, rhs: f32) -> <Vec3<T> as Mul<f32>>::Output {
-        Vec3{
-            x: FromPrimitive::from_f32(rhs * self.x.to_f32().unwrap()).unwrap(),
-            y: FromPrimitive::from_f32(rhs * self.y.to_f32().unwrap()).unwrap(),
-            z: FromPrimitive::from_f32(rhs * self.z.to_f32().unwrap()).unwrap(),
-        }
+impl<T: Scalar> Mul<f32> for &Vec3<T> {
+    type Output = Vec3<T>;
+    fn mul(self, rhs: f32) -> Vec3<T> {
+        *self * rhs
     }
 }
 
-impl<'a, T: Mul + FromPrimitive + ToPrimitive> Mul<f32> for &'a Vec3<T> where <T as Mul>::Output: FromPrimitive {
+impl<T: Scalar> Mul<f32> for Vec3<T> {
     type Output = Vec3<T>;
     fn mul(self, rhs: f32) -> Vec3<T> {
         Vec3{
-            x: FromPrimitive::from_f32(rhs * self.x.to_f32().unwrap()).unwrap(),
-            y: FromPrimitive::from_f32(rhs * self.y.to_f32().unwrap()).unwrap(),
-            z: FromPrimitive::from_f32(rhs * self.z.to_f32().unwrap()).unwrap(),
+            x: T::from_f32(rhs * self.x.to_f32()),
+            y: T::from_f32(rhs * self.y.to_f32()),
+            z: T::from_f32(rhs * self.z.to_f32()),
         }
     }
 }
 
-impl<'a, T: Mul + Clone + Copy> BitXor for &'a Vec3<T> where <T as Mul>::Output: Sub {
-    type Output = Vec3<<<T as Mul>::Output as Sub>::Output>;
-    fn bitxor(self, rhs: &Vec3<T>) -> <Self as BitXor>::Output {
-        let sx = self.x;
-        let sy = self.y;
-        let sz = self.z;
-        let rx = rhs.x;
-        let ry = rhs.y;
-        let rz = rhs.z;
-        Vec3{
-            x: sy * rz - sz * ry,
-            y: sz * rx - sx * rz,
-            z: sx * ry - sy * rx
-        }
+impl<T: Scalar> BitXor for &Vec3<T> {
+    type Output = Vec3<T>;
+    fn bitxor(self, rhs: &Vec3<T>) -> Vec3<T> {
+        *self ^ *rhs
     }
 }
 
-impl<T: Mul + Clone + Copy> BitXor for Vec3<T> where <T as Mul>::Output: Sub {
-    type Output = Vec3<<<T as Mul>::Output as Sub>::Output>;
-    fn bitxor(self, rhs: Vec3<T>) -> <Self as BitXor>::Output {
-        let sx = self.x;
-        let sy = self.y;
-        let sz = self.z;
-        let rx = rhs.x;
-        let ry = rhs.y;
-        let rz = rhs.z;
+impl<T: Scalar> BitXor for Vec3<T> {
+    type Output = Vec3<T>;
+    fn bitxor(self, rhs: Vec3<T>) -> Vec3<T> {
         Vec3{
-            x: sy * rz - sz * ry,
-            y: sz * rx - sx * rz,
-            z: sx * ry - sy * rx
+            x: self.y * rhs.z - self.z * rhs.y,
+            y: self.z * rhs.x - self.x * rhs.z,
+            z: self.x * rhs.y - self.y * rhs.x
         }
     }
 }
 
-impl<T: Clone + Copy + NumCast + Mul + Add + Sub + FromPrimitive + ToPrimitive> Vec3<T>
-where <T as Mul>::Output: Mul + Add + ToPrimitive + FromPrimitive + NumCast,
-      <<T as Mul>::Output as Add>::Output: NumCast,
-      <<T as Mul>::Output as Mul>::Output: NumCast
-{
+impl<T: Scalar> Vec3<T> {
     pub fn new(x: T, y: T, z: T) -> Vec3<T> {
-        Vec3 {x: x, y: y, z: z}
+        Vec3 {x, y, z}
     }
 
     pub fn norm(&self) -> f32 {
-        Float::sqrt((self.x * self.x).to_f32().unwrap() + (self.y * self.y).to_f32().unwrap() + (self.z * self.z).to_f32().unwrap())
+        ((self.x * self.x).to_f32() + (self.y * self.y).to_f32() + (self.z * self.z).to_f32()).sqrt()
     }
 
     pub fn vec_mul(&self, rhs: &Self) -> T {
-        NumCast::from(self.x * rhs.x + NumCast::from(self.y * rhs.y + self.z * rhs.z).unwrap()).unwrap()
+        self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
     }
 
     pub fn normalize(&self) -> Vec3<T> {
         let norm: f32 = 1f32 / self.norm();
         self * norm
     }
-    
-    pub fn to_vec2(&self) -> Vec2<T> {
+
+    pub fn to_vec2(self) -> Vec2<T> {
         Vec2 {x: self.x, y: self.y}
     }
 
-    pub fn to<K: Clone + NumCast + Mul + Add + Sub>(&self) -> Vec3<K> {
+    pub fn to<K: Scalar>(&self) -> Vec3<K> {
         Vec3 {
-            x: NumCast::from(self.x).unwrap(),
-            y: NumCast::from(self.y).unwrap(),
-            z: NumCast::from(self.z).unwrap(),
+            x: self.x.cast(),
+            y: self.y.cast(),
+            z: self.z.cast(),
         }
     }
 
     pub fn scale(&mut self, width: T, height: T, depth: T) {
-        self.x = NumCast::from(self.x * width * NumCast::from(0.5f32).unwrap()).unwrap();
-        self.y = NumCast::from(self.y * height * NumCast::from(0.5f32).unwrap()).unwrap();
-        self.z = NumCast::from(self.z * depth * NumCast::from(0.5f32).unwrap()).unwrap();
+        self.x = T::from_f64((self.x * width).to_f64() * 0.5);
+        self.y = T::from_f64((self.y * height).to_f64() * 0.5);
+        self.z = T::from_f64((self.z * depth).to_f64() * 0.5);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Vec4<T> {
+    pub x: T,
+    pub y: T,
+    pub z: T,
+    pub w: T,
+}
+
+impl<T: Scalar> Vec4<T> {
+    pub fn new(x: T, y: T, z: T, w: T) -> Vec4<T> {
+        Vec4 {x, y, z, w}
+    }
+
+    pub fn from_vec3(v: Vec3<T>, w: T) -> Vec4<T> {
+        Vec4 {x: v.x, y: v.y, z: v.z, w}
+    }
+
+    pub fn to_vec3(self) -> Vec3<T> {
+        Vec3 {x: self.x, y: self.y, z: self.z}
+    }
+
+    // Perspective division back to 3D
+    pub fn project(&self) -> Vec3f {
+        let w = self.w.to_f32();
+        Vec3::new(self.x.to_f32() / w, self.y.to_f32() / w, self.z.to_f32() / w)
+    }
+
+    pub fn to<K: Scalar>(&self) -> Vec4<K> {
+        Vec4 {
+            x: self.x.cast(),
+            y: self.y.cast(),
+            z: self.z.cast(),
+            w: self.w.cast(),
+        }
+    }
+}
+
+impl<T: Add<Output = T>> Add for Vec4<T> {
+    type Output = Vec4<T>;
+    fn add(self, rhs: Vec4<T>) -> Vec4<T> {
+        Vec4{x: self.x + rhs.x, y: self.y + rhs.y, z: self.z + rhs.z, w: self.w + rhs.w}
+    }
+}
+
+impl<T: Sub<Output = T>> Sub for Vec4<T> {
+    type Output = Vec4<T>;
+    fn sub(self, rhs: Vec4<T>) -> Vec4<T> {
+        Vec4{x: self.x - rhs.x, y: self.y - rhs.y, z: self.z - rhs.z, w: self.w - rhs.w}
+    }
+}
+
+impl<T: Scalar> Mul<f32> for Vec4<T> {
+    type Output = Vec4<T>;
+    fn mul(self, rhs: f32) -> Vec4<T> {
+        Vec4{
+            x: T::from_f32(rhs * self.x.to_f32()),
+            y: T::from_f32(rhs * self.y.to_f32()),
+            z: T::from_f32(rhs * self.z.to_f32()),
+            w: T::from_f32(rhs * self.w.to_f32()),
+        }
+    }
+}
+
+// Row-major 4x4 matrix, vectors are columns: m * v
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat4 {
+    m: [[f32; 4]; 4],
+}
+
+impl Index<usize> for Mat4 {
+    type Output = [f32; 4];
+
+    fn index(&self, row: usize) -> &[f32; 4] {
+        &self.m[row]
+    }
+}
+
+impl IndexMut<usize> for Mat4 {
+    fn index_mut(&mut self, row: usize) -> &mut [f32; 4] {
+        &mut self.m[row]
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Mat4;
+    fn mul(self, rhs: Mat4) -> Mat4 {
+        let mut result = Mat4::zero();
+        for i in 0..4 {
+            for j in 0..4 {
+                result.m[i][j] = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        result
+    }
+}
+
+impl Mul<Vec4f> for Mat4 {
+    type Output = Vec4f;
+    fn mul(self, v: Vec4f) -> Vec4f {
+        let row = |i: usize| self.m[i][0] * v.x + self.m[i][1] * v.y + self.m[i][2] * v.z + self.m[i][3] * v.w;
+        Vec4::new(row(0), row(1), row(2), row(3))
+    }
+}
+
+impl Mat4 {
+    pub fn new(m: [[f32; 4]; 4]) -> Mat4 {
+        Mat4 {m}
+    }
+
+    pub fn zero() -> Mat4 {
+        Mat4 {m: [[0.; 4]; 4]}
+    }
+
+    pub fn identity() -> Mat4 {
+        let mut result = Mat4::zero();
+        for i in 0..4 {
+            result.m[i][i] = 1.;
+        }
+        result
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut result = Mat4::zero();
+        for i in 0..4 {
+            for j in 0..4 {
+                result.m[i][j] = self.m[j][i];
+            }
+        }
+        result
+    }
+
+    // Gauss-Jordan elimination with partial pivoting, None for singular matrices
+    pub fn inverse(&self) -> Option<Mat4> {
+        let mut a = self.m;
+        let mut inv = Mat4::identity().m;
+        for col in 0..4 {
+            let pivot = (col..4).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs())).unwrap();
+            if a[pivot][col].abs() < f32::EPSILON {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+            let k = 1. / a[col][col];
+            for j in 0..4 {
+                a[col][j] *= k;
+                inv[col][j] *= k;
+            }
+            for i in 0..4 {
+                if i == col {
+                    continue;
+                }
+                let f = a[i][col];
+                for j in 0..4 {
+                    a[i][j] -= f * a[col][j];
+                    inv[i][j] -= f * inv[col][j];
+                }
+            }
+        }
+        Some(Mat4 {m: inv})
+    }
+
+    pub fn transform_point(&self, v: &Vec3f) -> Vec4f {
+        *self * Vec4::from_vec3(*v, 1.)
+    }
+
+    pub fn transform_dir(&self, v: &Vec3f) -> Vec3f {
+        (*self * Vec4::from_vec3(*v, 0.)).to_vec3()
+    }
+
+    // World to camera space, the camera sits at eye looking at center with -z
+    pub fn lookat(eye: &Vec3f, center: &Vec3f, up: &Vec3f) -> Mat4 {
+        let z = (*eye - *center).normalize();
+        let x = (*up ^ z).normalize();
+        let y = (z ^ x).normalize();
+        let mut result = Mat4::identity();
+        for (i, axis) in [x, y, z].iter().enumerate() {
+            result.m[i][0] = axis.x;
+            result.m[i][1] = axis.y;
+            result.m[i][2] = axis.z;
+            result.m[i][3] = -axis.vec_mul(center);
+        }
+        result
+    }
+
+    // Maps the [-1, 1] cube onto the [x, x + w] * [y, y + h] * [0, depth] box
+    pub fn viewport(x: f32, y: f32, w: f32, h: f32, depth: f32) -> Mat4 {
+        let mut result = Mat4::identity();
+        result.m[0][3] = x + w / 2.;
+        result.m[1][3] = y + h / 2.;
+        result.m[2][3] = depth / 2.;
+        result.m[0][0] = w / 2.;
+        result.m[1][1] = h / 2.;
+        result.m[2][2] = depth / 2.;
+        result
+    }
+
+    // Central projection onto the z = 0 plane with the camera at distance c on the z axis,
+    // c = infinity gives the orthographic one
+    pub fn projection(c: f32) -> Mat4 {
+        let mut result = Mat4::identity();
+        result.m[3][2] = -1. / c;
+        result
     }
 }
 
@@ -187,3 +393,4 @@ pub type Vec2f = Vec2<f32>;
 pub type Vec2i = Vec2<i32>;
 pub type Vec3f = Vec3<f32>;
 pub type Vec3i = Vec3<i32>;
+pub type Vec4f = Vec4<f32>;
@@ -0,0 +1,155 @@
+use crate::error::{Error, Result};
+use crate::tgaimage::{Color, Image};
+use crate::vec::Scalar;
+
+pub trait Depth: Scalar {
+    fn farthest() -> Self;
+    fn nearest() -> Self;
+}
+
+impl Depth for i32 {
+    #[inline]
+    fn farthest() -> i32 { i32::MIN }
+    #[inline]
+    fn nearest() -> i32 { i32::MAX }
+}
+
+impl Depth for f32 {
+    #[inline]
+    fn farthest() -> f32 { f32::NEG_INFINITY }
+    #[inline]
+    fn nearest() -> f32 { f32::INFINITY }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum DepthTest {
+    Less,
+    LessEqual,
+    Greater,
+    Always,
+}
+
+impl DepthTest {
+    #[inline]
+    pub fn passes<T: PartialOrd>(&self, incoming: T, stored: T) -> bool {
+        match *self {
+            DepthTest::Less => incoming < stored,
+            DepthTest::LessEqual => incoming <= stored,
+            DepthTest::Greater => incoming > stored,
+            DepthTest::Always => true,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ZBuffer<T = i32> {
+    data: Vec<T>,
+    pub width: usize,
+    pub height: usize,
+    pub test: DepthTest,
+}
+
+impl<T: Depth> ZBuffer<T> {
+    // Bigger z is closer to the viewer, so the buffer starts at the farthest value
+    pub fn new(w: usize, h: usize) -> Self {
+        ZBuffer::with_test(w, h, DepthTest::Greater)
+    }
+
+    pub fn with_test(w: usize, h: usize, test: DepthTest) -> Self {
+        let init = match test {
+            DepthTest::Less | DepthTest::LessEqual => Depth::nearest(),
+            DepthTest::Greater | DepthTest::Always => Depth::farthest(),
+        };
+        ZBuffer {data: vec![init; w * h], width: w, height: h, test}
+    }
+
+    pub fn clear(&mut self, value: T) {
+        for v in self.data.iter_mut() {
+            *v = value;
+        }
+    }
+
+    #[inline]
+    pub fn val(&self, x: usize, y: usize) -> &T {
+        &self.data[x + y * self.width]
+    }
+
+    #[inline]
+    pub fn val_mut(&mut self, x: usize, y: usize) -> &mut T {
+        &mut self.data[x + y * self.width]
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Result<T> {
+        if x >= self.width || y >= self.height {
+            return Err(Error::WrongCoords)
+        }
+        Ok(*self.val(x, y))
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, value: T) -> Result<()> {
+        if x >= self.width || y >= self.height {
+            return Err(Error::WrongCoords)
+        }
+        *self.val_mut(x, y) = value;
+        Ok(())
+    }
+
+    /// # Safety
+    ///
+    /// `x` and `y` must be within the buffer dimensions.
+    pub unsafe fn get_unchecked(&self, x: usize, y: usize) -> T {
+        *self.data.get_unchecked(x + y * self.width)
+    }
+
+    /// # Safety
+    ///
+    /// `x` and `y` must be within the buffer dimensions.
+    pub unsafe fn set_unchecked(&mut self, x: usize, y: usize, value: T) {
+        *self.data.get_unchecked_mut(x + y * self.width) = value;
+    }
+
+    // Stores the value and returns true if it passes the depth test against the stored one
+    #[inline]
+    pub fn test_and_set(&mut self, x: usize, y: usize, value: T) -> bool {
+        let idx = x + y * self.width;
+        if self.test.passes(value, self.data[idx]) {
+            self.data[idx] = value;
+            true
+        } else {
+            false
+        }
+    }
+
+    #[inline]
+    fn is_cleared(v: T) -> bool {
+        v == Depth::farthest() || v == Depth::nearest()
+    }
+
+    // Grayscale dump for debugging, written values are stretched over the whole 0..255 range
+    pub fn to_image(&self) -> Image {
+        let mut lo = f32::INFINITY;
+        let mut hi = f32::NEG_INFINITY;
+        for v in self.data.iter() {
+            if !ZBuffer::is_cleared(*v) {
+                let v = v.to_f32();
+                if v < lo { lo = v; }
+                if v > hi { hi = v; }
+            }
+        }
+        let span = if hi > lo { hi - lo } else { 1. };
+
+        let mut img = Image::new(self.width, self.height, 1);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let v = *self.val(x, y);
+                let gray = if ZBuffer::is_cleared(v) {
+                    0u8
+                } else {
+                    ((v.to_f32() - lo) / span * 255. + 0.5) as u8
+                };
+                img.set(x, y, &Color::from_raw(&[gray], 1)).unwrap();
+            }
+        }
+        img
+    }
+}
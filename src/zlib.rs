@@ -0,0 +1,508 @@
+// Minimal zlib (RFC 1950) and deflate (RFC 1951) support for PNG
+
+use crate::error::{Error, Result};
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+// Running CRC-32, start with 0
+pub fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut c = !crc;
+    for &b in data {
+        c = CRC_TABLE[((c ^ b as u32) & 0xff) as usize] ^ (c >> 8);
+    }
+    !c
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 is the largest run that cannot overflow b before the modulo
+    for chunk in data.chunks(5552) {
+        for &x in chunk {
+            a += x as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+// Base value and extra bits of the length codes 257..285 and the distance codes 0..29
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: usize = 15;
+// How many earlier positions with the same hash are tried, trades speed for ratio
+const MAX_CHAIN: usize = 64;
+
+// Deflate streams are packed starting from the least significant bit
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u32,
+    nbits: u32,
+}
+
+impl BitWriter {
+    fn bits(&mut self, value: u32, n: u32) {
+        self.acc |= value << self.nbits;
+        self.nbits += n;
+        while self.nbits >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.nbits -= 8;
+        }
+    }
+
+    // Huffman codes go most significant bit first
+    fn code(&mut self, code: u32, n: u32) {
+        self.bits(code.reverse_bits() >> (32 - n), n);
+    }
+
+    fn flush(mut self) -> Vec<u8> {
+        if self.nbits > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+// Fixed Huffman code of a literal/length symbol
+fn fixed_literal(w: &mut BitWriter, sym: u16) {
+    let sym = sym as u32;
+    match sym {
+        0..=143 => w.code(0x30 + sym, 8),
+        144..=255 => w.code(0x190 + sym - 144, 9),
+        256..=279 => w.code(sym - 256, 7),
+        _ => w.code(0xc0 + sym - 280, 8),
+    }
+}
+
+fn write_match(w: &mut BitWriter, len: usize, dist: usize) {
+    let li = LENGTH_BASE.iter().rposition(|&b| b as usize <= len).unwrap();
+    fixed_literal(w, 257 + li as u16);
+    w.bits((len - LENGTH_BASE[li] as usize) as u32, LENGTH_EXTRA[li] as u32);
+    let di = DIST_BASE.iter().rposition(|&b| b as usize <= dist).unwrap();
+    w.code(di as u32, 5);
+    w.bits((dist - DIST_BASE[di] as usize) as u32, DIST_EXTRA[di] as u32);
+}
+
+// Hash chains over the last window, indexed by the first three bytes at each position
+struct Matcher<'a> {
+    data: &'a [u8],
+    head: Vec<usize>,
+    prev: Vec<usize>,
+}
+
+impl Matcher<'_> {
+    #[inline]
+    fn hash(&self, pos: usize) -> usize {
+        let d = self.data;
+        let v = (d[pos] as u32) << 16 | (d[pos + 1] as u32) << 8 | d[pos + 2] as u32;
+        (v.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, pos: usize) {
+        if pos + MIN_MATCH <= self.data.len() {
+            let h = self.hash(pos);
+            self.prev[pos % WINDOW_SIZE] = self.head[h];
+            self.head[h] = pos;
+        }
+    }
+
+    // Longest earlier match as (length, distance)
+    fn find(&self, pos: usize) -> (usize, usize) {
+        let data = self.data;
+        let mut best = (0, 0);
+        if pos + MIN_MATCH > data.len() {
+            return best;
+        }
+        let max_len = MAX_MATCH.min(data.len() - pos);
+        let mut cand = self.head[self.hash(pos)];
+        for _ in 0..MAX_CHAIN {
+            if cand == usize::MAX || pos - cand > WINDOW_SIZE {
+                break;
+            }
+            let len = data[cand..].iter().zip(data[pos..pos + max_len].iter()).take_while(|(a, b)| a == b).count();
+            if len > best.0 {
+                best = (len, pos - cand);
+                if len == max_len {
+                    break;
+                }
+            }
+            // Slots get reused once the window slides past them
+            let next = self.prev[cand % WINDOW_SIZE];
+            if next >= cand {
+                break;
+            }
+            cand = next;
+        }
+        best
+    }
+}
+
+// Single block with the fixed Huffman codes, matches are found greedily
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut w = BitWriter {out: Vec::with_capacity(data.len() / 2), acc: 0, nbits: 0};
+    // Final block, fixed codes
+    w.bits(1, 1);
+    w.bits(1, 2);
+
+    let mut matcher = Matcher {data, head: vec![usize::MAX; 1 << HASH_BITS], prev: vec![usize::MAX; WINDOW_SIZE]};
+    let mut pos = 0;
+    while pos < data.len() {
+        let (len, dist) = matcher.find(pos);
+        let step = if len >= MIN_MATCH {
+            write_match(&mut w, len, dist);
+            len
+        } else {
+            fixed_literal(&mut w, data[pos] as u16);
+            1
+        };
+        for p in pos..pos + step {
+            matcher.insert(p);
+        }
+        pos += step;
+    }
+    fixed_literal(&mut w, 256);
+    w.flush()
+}
+
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    // Deflate with a 32K window, default compression level
+    let mut out = vec![0x78, 0x9c];
+    out.extend(deflate(data));
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+const CORRUPT: Error = Error::InvalidFormat("Corrupt deflate stream");
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    acc: u32,
+    nbits: u32,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, n: u32) -> Result<u32> {
+        while self.nbits < n {
+            let byte = *self.data.get(self.pos).ok_or(CORRUPT)?;
+            self.acc |= (byte as u32) << self.nbits;
+            self.pos += 1;
+            self.nbits += 8;
+        }
+        let v = self.acc & ((1u64 << n) - 1) as u32;
+        self.acc >>= n;
+        self.nbits -= n;
+        Ok(v)
+    }
+
+    // Stored blocks start on a byte boundary
+    fn align(&mut self) {
+        self.acc = 0;
+        self.nbits = 0;
+    }
+}
+
+// Canonical Huffman code given by the number of codes of each length and the symbols in code order
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Huffman> {
+        let mut counts = [0u16; 16];
+        for &l in lengths {
+            counts[l as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut left = 1i32;
+        for &c in counts[1..].iter() {
+            left = (left << 1) - c as i32;
+            if left < 0 {
+                return Err(CORRUPT);
+            }
+        }
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (sym, &l) in lengths.iter().enumerate() {
+            if l != 0 {
+                symbols[offsets[l as usize] as usize] = sym as u16;
+                offsets[l as usize] += 1;
+            }
+        }
+        Ok(Huffman {counts, symbols})
+    }
+
+    // Codes are read one bit at a time, most significant first
+    fn decode(&self, r: &mut BitReader) -> Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= r.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(CORRUPT)
+    }
+}
+
+fn fixed_codes() -> Result<(Huffman, Huffman)> {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    Ok((Huffman::new(&lengths)?, Huffman::new(&[5; 30])?))
+}
+
+fn dynamic_codes(r: &mut BitReader) -> Result<(Huffman, Huffman)> {
+    const ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+    let nlen = r.bits(5)? as usize + 257;
+    let ndist = r.bits(5)? as usize + 1;
+    let ncode = r.bits(4)? as usize + 4;
+    if nlen > 286 || ndist > 30 {
+        return Err(CORRUPT);
+    }
+    let mut lengths = [0u8; 19];
+    for &idx in ORDER[..ncode].iter() {
+        lengths[idx] = r.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&lengths)?;
+
+    let mut lengths = vec![0u8; nlen + ndist];
+    let mut i = 0;
+    while i < nlen + ndist {
+        let sym = code_lengths.decode(r)?;
+        let (value, repeat) = match sym {
+            0..=15 => (sym as u8, 1),
+            16 => (*lengths.get(i.wrapping_sub(1)).ok_or(CORRUPT)?, 3 + r.bits(2)? as usize),
+            17 => (0, 3 + r.bits(3)? as usize),
+            _ => (0, 11 + r.bits(7)? as usize),
+        };
+        if i + repeat > nlen + ndist {
+            return Err(CORRUPT);
+        }
+        lengths[i..i + repeat].fill(value);
+        i += repeat;
+    }
+    if lengths[256] == 0 {
+        return Err(CORRUPT);
+    }
+    Ok((Huffman::new(&lengths[..nlen])?, Huffman::new(&lengths[nlen..])?))
+}
+
+fn inflate_block(r: &mut BitReader, out: &mut Vec<u8>, max: usize, lit: &Huffman, dist: &Huffman) -> Result<()> {
+    loop {
+        let sym = lit.decode(r)? as usize;
+        if sym < 256 {
+            if out.len() == max {
+                return Err(CORRUPT);
+            }
+            out.push(sym as u8);
+            continue;
+        }
+        if sym == 256 {
+            return Ok(());
+        }
+        let li = sym - 257;
+        if li >= LENGTH_BASE.len() {
+            return Err(CORRUPT);
+        }
+        let len = LENGTH_BASE[li] as usize + r.bits(LENGTH_EXTRA[li] as u32)? as usize;
+        let di = dist.decode(r)? as usize;
+        if di >= DIST_BASE.len() {
+            return Err(CORRUPT);
+        }
+        let d = DIST_BASE[di] as usize + r.bits(DIST_EXTRA[di] as u32)? as usize;
+        if d > out.len() || out.len() + len > max {
+            return Err(CORRUPT);
+        }
+        // Copies may overlap their own output
+        let start = out.len() - d;
+        for k in 0..len {
+            out.push(out[start + k]);
+        }
+    }
+}
+
+// Streams that inflate to more than max bytes are rejected as corrupt
+pub fn inflate(data: &[u8], max: usize) -> Result<Vec<u8>> {
+    let mut r = BitReader {data, pos: 0, acc: 0, nbits: 0};
+    let mut out = Vec::with_capacity(data.len().saturating_mul(4).min(max));
+    loop {
+        let last = r.bits(1)? == 1;
+        match r.bits(2)? {
+            0 => {
+                r.align();
+                let header = data.get(r.pos..r.pos + 4).ok_or(CORRUPT)?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                if len != !u16::from_le_bytes([header[2], header[3]]) {
+                    return Err(CORRUPT);
+                }
+                r.pos += 4;
+                if out.len() + len as usize > max {
+                    return Err(CORRUPT);
+                }
+                out.extend_from_slice(data.get(r.pos..r.pos + len as usize).ok_or(CORRUPT)?);
+                r.pos += len as usize;
+            },
+            1 => {
+                let (lit, dist) = fixed_codes()?;
+                inflate_block(&mut r, &mut out, max, &lit, &dist)?;
+            },
+            2 => {
+                let (lit, dist) = dynamic_codes(&mut r)?;
+                inflate_block(&mut r, &mut out, max, &lit, &dist)?;
+            },
+            _ => return Err(CORRUPT),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+pub fn decompress(data: &[u8], max: usize) -> Result<Vec<u8>> {
+    if data.len() < 6 {
+        return Err(CORRUPT);
+    }
+    let (cmf, flg) = (data[0], data[1]);
+    // Deflate with at most a 32K window, no preset dictionary
+    if cmf & 0x0f != 8 || cmf >> 4 > 7 || !(cmf as u16 * 256 + flg as u16).is_multiple_of(31) || flg & 0x20 != 0 {
+        return Err(Error::InvalidFormat("Unsupported zlib stream"));
+    }
+    let out = inflate(&data[2..], max)?;
+    let tail = &data[data.len() - 4..];
+    if adler32(&out) != u32::from_be_bytes([tail[0], tail[1], tail[2], tail[3]]) {
+        return Err(Error::InvalidFormat("zlib checksum mismatch"));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Deterministic bytes that mix random stretches with repeats at distances up to the window size
+    fn sample(len: usize) -> Vec<u8> {
+        let mut seed = 0x2545f491u32;
+        let mut out = Vec::with_capacity(len);
+        while out.len() < len {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            let r = (seed >> 16) as usize;
+            if r.is_multiple_of(4) && !out.is_empty() {
+                let dist = 1 + r % out.len().min(WINDOW_SIZE);
+                for _ in 0..(r % 300).min(len - out.len()) {
+                    out.push(out[out.len() - dist]);
+                }
+            } else {
+                out.push(r as u8);
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(0, b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(crc32(0, b"1234"), b"56789"), 0xcbf43926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+        assert_eq!(adler32(&[]), 1);
+    }
+
+    #[test]
+    fn deflate_round_trip() {
+        let sizes = [0, 1, 2, 3, 100, WINDOW_SIZE - 1, WINDOW_SIZE, WINDOW_SIZE + 1, 3 * WINDOW_SIZE + 17];
+        for len in sizes {
+            let data = sample(len);
+            assert_eq!(inflate(&deflate(&data), len).unwrap(), data, "{} bytes", len);
+            assert_eq!(decompress(&compress(&data), len).unwrap(), data, "{} bytes", len);
+        }
+        // Runs longer than the longest match and than the window
+        let zeros = vec![0u8; 2 * WINDOW_SIZE + 1000];
+        assert_eq!(inflate(&deflate(&zeros), zeros.len()).unwrap(), zeros);
+    }
+
+    #[test]
+    fn output_limit() {
+        let data = sample(1000);
+        assert!(decompress(&compress(&data), 999).is_err());
+        assert!(inflate(&deflate(&vec![7u8; 1000]), 999).is_err());
+    }
+
+    #[test]
+    fn stored_and_dynamic_blocks() {
+        // zlib.compress(b"stored block", 0)
+        let stored = [0x78, 0x01, 0x01, 0x0c, 0x00, 0xf3, 0xff, 0x73, 0x74, 0x6f, 0x72, 0x65, 0x64, 0x20, 0x62,
+                      0x6c, 0x6f, 0x63, 0x6b, 0x1f, 0x80, 0x04, 0xbd];
+        assert_eq!(decompress(&stored, 100).unwrap(), b"stored block");
+        assert!(decompress(&stored, 11).is_err());
+
+        // zlib.compress(b"".join(bytes([i % 7 + 97, i % 13 + 97]) for i in range(200)), 9)
+        let dynamic = [
+            0x78, 0xda, 0xdd, 0x8e, 0xc9, 0x0d, 0x02, 0x00, 0x10, 0x02, 0x6b, 0x65, 0x0f, 0xf6, 0xee,
+            0xff, 0xab, 0x31, 0x31, 0x6a, 0x0b, 0xbe, 0x78, 0xc0, 0x64, 0x00, 0x44, 0x54, 0xcd, 0xdc,
+            0xc9, 0x08, 0xa4, 0x94, 0xb6, 0x8d, 0x2f, 0x2f, 0xf0, 0x53, 0x25, 0x4a, 0x5a, 0xc7, 0xd6,
+            0x8f, 0x08, 0x81, 0x8a, 0xa9, 0x1b, 0x3d, 0x98, 0x51, 0x68, 0x19, 0x5d, 0x3b, 0x07, 0x25,
+            0x14, 0x26, 0xae, 0xb4, 0xf0, 0x64, 0x45, 0x63, 0x64, 0xf5, 0x0c, 0x2e, 0xd4, 0x78, 0x47,
+            0x7a, 0xb1, 0x63, 0xb0, 0x72, 0x9f, 0xf9, 0x0b, 0x4e, 0x2b, 0x6f, 0x4e, 0x2c, 0xee, 0x5b,
+            0xf2, 0x54, 0xa6, 0x96, 0xb5, 0x0f, 0x37, 0x0e, 0x7f, 0xfb, 0xfa, 0x01, 0x21, 0xa0, 0x9e,
+            0x7f,
+        ];
+        let expected: Vec<u8> = (0..200u8).flat_map(|i| [i % 7 + 97, i % 13 + 97]).collect();
+        assert_eq!(decompress(&dynamic, 400).unwrap(), expected);
+    }
+
+    #[test]
+    fn corrupt_streams() {
+        let mut data = compress(b"checksum");
+        *data.last_mut().unwrap() ^= 1;
+        assert!(decompress(&data, 100).is_err());
+        let data = compress(&sample(500));
+        assert!(decompress(&data[..data.len() / 2], 500).is_err());
+    }
+}